@@ -137,9 +137,18 @@ impl Vod {
 }
 
 impl crate::common::Vod for Vod {
+    fn id(&self) -> String {
+        self.title_no.to_string()
+    }
+
+    /// The station number, which AfreecaTV uses to identify the broadcaster
+    fn channel(&self) -> String {
+        format!("afreecatv/{}", self.station_no)
+    }
+
     fn comments(&self) -> Box<dyn crate::common::ChatIterator> {
         let xml = crate::common::CLIENT
-            .get(&self.info_url())
+            .get(self.info_url())
             .header(reqwest::header::COOKIE, DUMMY_COOKIE)
             .send()
             .unwrap()
@@ -159,8 +168,17 @@ impl crate::common::Vod for Vod {
         Box::new(ChatIterator {
             rows,
             current_offset: 0,
+            error: None,
         })
     }
+
+    fn link(&self, offset: f64) -> String {
+        format!(
+            "https://vod.afreecatv.com/player/{}?change_second={}",
+            self.title_no,
+            offset.max(0.0) as u64
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -172,6 +190,8 @@ struct Row {
 struct ChatIterator {
     rows: std::collections::VecDeque<Row>,
     current_offset: u16,
+    /// The first segment that could not be loaded
+    error: Option<String>,
 }
 
 impl ChatIterator {
@@ -205,19 +225,32 @@ impl ChatIterator {
                     user: Some(user.to_string()),
                     body: body.to_string(),
                     timestamp: Some(timestamp + time_offset as f64),
+                    ..Default::default()
                 })
             })
             .collect())
     }
 
-    fn load_chunk(row: Row, time_offset: u16) -> Vec<crate::common::Message> {
+    /// The messages of every segment of a row, along with the first error if any segment could
+    /// not be loaded
+    fn load_chunk(row: Row, time_offset: u16) -> (Vec<crate::common::Message>, Option<String>) {
         let segment_diff = 300;
         let timings: Vec<u16> = (0..row.duration).step_by(segment_diff).collect();
-        timings
+        let segments: Vec<Result<Vec<crate::common::Message>, String>> = timings
             .par_iter()
-            .flat_map(|t| Self::get_segment(&row.key, *t, time_offset))
-            .flatten()
-            .collect()
+            .map(|t| Self::get_segment(&row.key, *t, time_offset).map_err(|e| e.to_string()))
+            .collect();
+        let mut messages = Vec::new();
+        let mut error = None;
+        for segment in segments {
+            match segment {
+                Ok(segment) => messages.extend(segment),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        (messages, error)
     }
 }
 
@@ -226,10 +259,18 @@ impl Iterator for ChatIterator {
     fn next(&mut self) -> Option<Self::Item> {
         let row = self.rows.pop_front()?;
         let duration = row.duration;
-        let item = ChatIterator::load_chunk(row, self.current_offset);
+        let (item, error) = ChatIterator::load_chunk(row, self.current_offset);
+        if let Some(e) = error {
+            eprintln!("{}", e);
+            self.error.get_or_insert(e);
+        }
         self.current_offset += duration;
         Some(item)
     }
 }
 
-impl crate::common::ChatIterator for ChatIterator {}
+impl crate::common::ChatIterator for ChatIterator {
+    fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
@@ -0,0 +1,54 @@
+use std::io::Write;
+
+/// A directory of chat logs, stored as one JSON message per line with a file per source
+pub struct Archive {
+    dir: std::path::PathBuf,
+}
+
+impl Archive {
+    pub fn new<P>(dir: P) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        P: Into<std::path::PathBuf>,
+    {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn path(&self, source: &str) -> std::path::PathBuf {
+        let name: String = source
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect();
+        self.dir.join(format!("{}.jsonl", name))
+    }
+
+    pub fn append(
+        &self,
+        source: &str,
+        messages: &[crate::common::Message],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.append_records(source, messages.iter().map(|message| message.to_json()))
+    }
+
+    pub fn append_records<I>(
+        &self,
+        source: &str,
+        records: I,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = serde_json::Value>,
+    {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(source))?;
+        for record in records {
+            writeln!(file, "{}", record)?;
+        }
+        Ok(())
+    }
+}
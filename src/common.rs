@@ -4,55 +4,393 @@ lazy_static::lazy_static! {
 
 pub const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/103.0.5060.114 Safari/537.36";
 
+use chrono::TimeZone;
+use colored::Colorize;
 use hhmmss::Hhmmss;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Message {
     pub user: Option<String>,
     pub body: String,
     pub timestamp: Option<f64>,
+    /// When the message was sent, in seconds since the Unix epoch, for chat that isn't tied to a
+    /// point in a VOD
+    pub sent: Option<f64>,
+    pub id: Option<String>,
+    pub channel: Option<String>,
+    pub color: Option<(u8, u8, u8)>,
+    pub badges: Vec<Badge>,
+    pub emotes: Vec<Emote>,
+    pub event: Option<Event>,
+    pub chapter: Option<String>,
+    pub reply: Option<Reply>,
+    pub bits: u64,
 }
 
-impl std::fmt::Display for Message {
+/// The message that a reply was sent in response to
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub id: Option<String>,
+    pub user: Option<String>,
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Badge {
+    pub name: String,
+    pub version: String,
+}
+
+/// The location of an emote within the body of a message, as an inclusive range of characters
+#[derive(Debug, Clone)]
+pub struct Emote {
+    pub id: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug)]
+pub enum Event {
+    /// A moderator deleted the message with the target ID
+    Deleted { target: String },
+    /// A moderator cleared the entire chat, or only the messages of a single user
+    Cleared {
+        user: Option<String>,
+        duration: Option<u64>,
+    },
+    /// The user subscribed, or announced a resubscription
+    Subscription {
+        months: Option<u64>,
+        tier: Option<String>,
+    },
+    /// The user gifted a subscription to a single recipient, or a number of subscriptions to the
+    /// rest of chat
+    GiftSub {
+        recipient: Option<String>,
+        count: Option<u64>,
+    },
+    /// The user raided the channel
+    Raid { viewers: Option<u64> },
+    /// Any other user notice, such as an announcement
+    Notice { kind: String },
+}
+
+impl Event {
+    /// Builds the event of a user notice from its `msg-id` and a lookup of its `msg-param-*`
+    /// parameters, as sent in the tags of IRC `USERNOTICE`s and the `userNoticeParams` of VOD
+    /// comments
+    pub fn from_notice<F>(kind: &str, param: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let number = |key: &str| param(key).and_then(|value| value.parse().ok());
+        match kind {
+            "sub" | "resub" => Event::Subscription {
+                months: number("msg-param-cumulative-months"),
+                tier: param("msg-param-sub-plan"),
+            },
+            "subgift" | "anonsubgift" => Event::GiftSub {
+                recipient: param("msg-param-recipient-display-name")
+                    .or_else(|| param("msg-param-recipient-user-name")),
+                count: None,
+            },
+            "submysterygift" | "anonsubmysterygift" => Event::GiftSub {
+                recipient: None,
+                count: number("msg-param-mass-gift-count"),
+            },
+            "raid" => Event::Raid {
+                viewers: number("msg-param-viewerCount"),
+            },
+            _ => Event::Notice {
+                kind: kind.to_owned(),
+            },
+        }
+    }
+
+    /// The name the event is filtered by
+    pub fn kind(&self) -> &str {
+        match self {
+            Event::Deleted { .. } => "deleted",
+            Event::Cleared { .. } => "cleared",
+            Event::Subscription { .. } => "sub",
+            Event::GiftSub { .. } => "giftsub",
+            Event::Raid { .. } => "raid",
+            Event::Notice { kind } => kind,
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Event::Deleted { target } => serde_json::json!({
+                "type": "deleted",
+                "target": target
+            }),
+            Event::Cleared { user, duration } => serde_json::json!({
+                "type": "cleared",
+                "user": user,
+                "duration": duration
+            }),
+            Event::Subscription { months, tier } => serde_json::json!({
+                "type": "sub",
+                "months": months,
+                "tier": tier
+            }),
+            Event::GiftSub { recipient, count } => serde_json::json!({
+                "type": "giftsub",
+                "recipient": recipient,
+                "count": count
+            }),
+            Event::Raid { viewers } => serde_json::json!({
+                "type": "raid",
+                "viewers": viewers
+            }),
+            Event::Notice { kind } => serde_json::json!({
+                "type": kind
+            }),
+        }
+    }
+}
+
+/// Names a subscription plan, e.g. "1000" -> "Tier 1"
+fn tier_name(plan: &str) -> String {
+    match plan {
+        "Prime" => String::from("Prime"),
+        "2000" => String::from("Tier 2"),
+        "3000" => String::from("Tier 3"),
+        _ => String::from("Tier 1"),
+    }
+}
+
+impl std::fmt::Display for Event {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::Deleted { .. } => write!(f, "<message deleted>"),
+            Event::Cleared {
+                user: Some(user),
+                duration: Some(duration),
+            } => write!(f, "<{} timed out for {}s>", user, duration),
+            Event::Cleared {
+                user: Some(user),
+                duration: None,
+            } => write!(f, "<{} banned>", user),
+            Event::Cleared { user: None, .. } => write!(f, "<chat cleared>"),
+            Event::Subscription { months, tier } => {
+                write!(f, "<subscribed")?;
+                if let Some(tier) = tier {
+                    write!(f, " with {}", tier_name(tier))?;
+                }
+                match months {
+                    Some(months) if *months > 1 => write!(f, " for {} months>", months),
+                    _ => write!(f, ">"),
+                }
+            }
+            Event::GiftSub {
+                recipient: Some(recipient),
+                ..
+            } => write!(f, "<gifted a sub to {}>", recipient),
+            Event::GiftSub {
+                recipient: None,
+                count,
+            } => write!(f, "<gifted {} subs>", count.unwrap_or(1)),
+            Event::Raid {
+                viewers: Some(viewers),
+            } => write!(f, "<raided with {} viewers>", viewers),
+            Event::Raid { viewers: None } => write!(f, "<raided>"),
+            Event::Notice { kind } => write!(f, "<{}>", kind),
+        }
+    }
+}
+
+/// Parses a hex color code such as `#1E90FF`
+pub fn parse_color(code: &str) -> Option<(u8, u8, u8)> {
+    let code = code.trim_start_matches('#');
+    Some((
+        u8::from_str_radix(code.get(0..2)?, 16).ok()?,
+        u8::from_str_radix(code.get(2..4)?, 16).ok()?,
+        u8::from_str_radix(code.get(4..6)?, 16).ok()?,
+    ))
+}
+
+impl Message {
+    pub fn is_match(&self, filter: &regex::Regex) -> bool {
+        filter.is_match(&self.body)
+            || match self.user.as_ref() {
+                Some(user) => filter.is_match(user),
+                None => false,
+            }
+    }
+
+    /// Whether the message is of one of the given kinds: an event such as "giftsub", "sub",
+    /// "raid" or "deleted", a "reply" or a "cheer". Always true if no kinds are given.
+    pub fn is_kind(&self, kinds: &[String]) -> bool {
+        kinds.is_empty()
+            || kinds.iter().any(|kind| match kind.as_str() {
+                "reply" => self.reply.is_some(),
+                "cheer" => self.bits > 0,
+                kind => self.event.as_ref().is_some_and(|e| e.kind() == kind),
+            })
+    }
+
+    /// Whether the message was sent during a chapter matching `chapter`, always true if no
+    /// chapter filter is given
+    pub fn in_chapter(&self, chapter: Option<&regex::Regex>) -> bool {
+        match (chapter, &self.chapter) {
+            (Some(filter), Some(chapter)) => filter.is_match(chapter),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "channel": self.channel,
+            "user": self.user,
+            "body": self.body,
+            "timestamp": self.timestamp,
+            "sent": self.sent,
+            "color": self
+                .color
+                .map(|(r, g, b)| format!("#{:02X}{:02X}{:02X}", r, g, b)),
+            "badges": self
+                .badges
+                .iter()
+                .map(|badge| format!("{}/{}", badge.name, badge.version))
+                .collect::<Vec<String>>(),
+            "emotes": self
+                .emotes
+                .iter()
+                .map(|emote| serde_json::json!({
+                    "id": emote.id,
+                    "start": emote.start,
+                    "end": emote.end
+                }))
+                .collect::<Vec<serde_json::Value>>(),
+            "event": self.event.as_ref().map(Event::to_json),
+            "chapter": self.chapter,
+            "reply": self.reply.as_ref().map(|reply| serde_json::json!({
+                "id": reply.id,
+                "user": reply.user,
+                "body": reply.body
+            })),
+            "bits": self.bits,
+        })
+    }
+
+    /// Writes the message as it is displayed, with `badges` ahead of the user and `body` in place
+    /// of the plain body, so that a renderer can substitute its own
+    pub fn write_with(
+        &self,
+        f: &mut dyn std::fmt::Write,
+        badges: &str,
+        body: &str,
+    ) -> std::fmt::Result {
         if let Some(seconds) = self.timestamp {
             let seconds = std::time::Duration::from_secs(seconds as u64);
             write!(f, "[{}]", seconds.hhmmss())?
+        } else if let Some(sent) = self.sent {
+            if let chrono::LocalResult::Single(sent) =
+                chrono::Local.timestamp_opt(sent as i64, (sent.fract() * 1e9) as u32)
+            {
+                write!(f, "[{}]", sent.format("%H:%M:%S"))?
+            }
         }
+        if let Some(channel) = &self.channel {
+            write!(f, "[#{}]", channel)?
+        }
+        write!(f, "{}", badges)?;
         if let Some(user) = &self.user {
-            write!(f, "[{}]", user)?
+            match self.color {
+                Some((r, g, b)) => write!(f, "[{}]", user.truecolor(r, g, b))?,
+                None => write!(f, "[{}]", user)?,
+            }
+        }
+        if self.bits > 0 {
+            write!(f, " {}", format!("<{} bits>", self.bits).purple())?
+        }
+        if let Some(reply) = &self.reply {
+            let parent = format!(
+                "(replying to @{}: {})",
+                reply.user.as_deref().unwrap_or("unknown"),
+                reply.body.as_deref().unwrap_or_default()
+            );
+            write!(f, " {}", parent.dimmed())?
+        }
+        match &self.event {
+            Some(event) => write!(f, " {} {}", event, body),
+            None => write!(f, " {}", body),
         }
-        write!(f, " {}", self.body)
+    }
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_with(f, "", &self.body)
     }
 }
 
 pub trait Vod: std::fmt::Display {
+    fn id(&self) -> String;
+    /// The channel that broadcast the VOD, which alerts are debounced by
+    fn channel(&self) -> String;
     fn comments(&self) -> Box<dyn ChatIterator>;
+    /// A link that opens the VOD `offset` seconds in
+    fn link(&self, offset: f64) -> String;
 }
 
-pub trait ChatIterator: Send + Iterator<Item = Vec<Message>> {}
+pub trait ChatIterator: Send + Iterator<Item = Vec<Message>> {
+    /// Why the chat ended before its last message, if it did
+    fn error(&self) -> Option<&str> {
+        None
+    }
+}
 
-pub fn print_iter<V>(vods: &[V], filter: &regex::Regex, showall: bool, sequence: &oqueue::Sequencer)
-where
+/// Prints the matching chat of every VOD, recording all of it into `archive` under
+/// `<channel>/<id>` as it is read
+#[allow(clippy::too_many_arguments)]
+pub fn print_iter<V>(
+    vods: &[V],
+    filter: &regex::Regex,
+    chapter: Option<&regex::Regex>,
+    kinds: &[String],
+    showall: bool,
+    alerts: Option<&crate::rules::Engine>,
+    archive: Option<&crate::archive::Archive>,
+    sequence: &oqueue::Sequencer,
+) where
     V: Vod + Sync,
 {
     rayon::scope_fifo(|t| {
         for vod in vods {
             t.spawn_fifo(|_| {
-                let comments = vod.comments().flatten();
                 let mut task = sequence.begin();
                 if !showall {
                     task.hold();
                 }
                 let v = vod.to_string();
                 writeln!(task, "{}", v);
-                for comment in comments.filter(|message| {
-                    filter.is_match(&message.body)
-                        || match message.user.as_ref() {
-                            Some(message) => filter.is_match(message),
-                            None => false,
+                let channel = vod.channel();
+                let id = vod.id();
+                let source = format!("{}/{}", channel, id);
+                for comment in vod
+                    .comments()
+                    .inspect(|messages| {
+                        if let Some(Err(e)) = archive.map(|a| a.append(&source, messages)) {
+                            eprintln!("{}", e);
                         }
-                }) {
+                    })
+                    .flatten()
+                    .inspect(|message| {
+                        alerts
+                            .iter()
+                            .for_each(|a| a.process(&channel, Some(&id), message))
+                    })
+                    .filter(|message| {
+                        message.is_match(filter)
+                            && message.in_chapter(chapter)
+                            && message.is_kind(kinds)
+                    })
+                {
                     task.release();
                     writeln!(task, "{}", comment);
                 }
@@ -61,3 +399,20 @@ where
         }
     });
 }
+
+/// The platform a VOD is from, for the modes that take one
+pub enum Platform {
+    Twitch,
+    Afreecatv,
+}
+
+impl std::str::FromStr for Platform {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "twitch" => Ok(Platform::Twitch),
+            "afreecatv" => Ok(Platform::Afreecatv),
+            _ => Err(r#"Expected: ["twitch", "afreecatv"]"#),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+lazy_static::lazy_static! {
+    /// User configuration, read from `$CHAT_READER_CONFIG` or `~/.config/chat_reader/config.json`
+    pub static ref CONFIG: serde_json::Value = match load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Unable to load config: {}", e);
+            serde_json::Value::Null
+        }
+    };
+}
+
+fn path() -> Option<std::path::PathBuf> {
+    if let Ok(path) = std::env::var("CHAT_READER_CONFIG") {
+        return Some(path.into());
+    }
+    let home = std::env::var("HOME").ok()?;
+    Some(
+        std::path::Path::new(&home)
+            .join(".config")
+            .join("chat_reader")
+            .join("config.json"),
+    )
+}
+
+fn load() -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    match path() {
+        Some(path) if path.exists() => Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+        _ => Ok(serde_json::Value::Null),
+    }
+}
+
+/// Directory for data that chat_reader learns over time, `$CHAT_READER_DATA` or
+/// `~/.local/share/chat_reader`
+pub fn data_dir() -> Option<std::path::PathBuf> {
+    if let Ok(path) = std::env::var("CHAT_READER_DATA") {
+        return Some(path.into());
+    }
+    let home = std::env::var("HOME").ok()?;
+    Some(
+        std::path::Path::new(&home)
+            .join(".local")
+            .join("share")
+            .join("chat_reader"),
+    )
+}
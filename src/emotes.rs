@@ -0,0 +1,670 @@
+const TWITCH_CDN: &str = "https://static-cdn.jtvnw.net/emoticons/v2";
+const BTTV_API: &str = "https://api.betterttv.net/3";
+const FFZ_API: &str = "https://api.frankerfacez.com/v1";
+const SEVENTV_API: &str = "https://7tv.io/v3";
+
+/// How long a downloaded emote set is used before it is fetched again
+const CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// An emote that can appear in chat, from Twitch or a third-party provider
+#[derive(Debug, Clone)]
+pub struct Emote {
+    pub provider: String,
+    pub id: String,
+    pub name: String,
+    pub url: String,
+}
+
+impl Emote {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "provider": self.provider,
+            "id": self.id,
+            "name": self.name,
+            "url": self.url
+        })
+    }
+
+    fn from_json(json: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            provider: json.get("provider")?.as_str()?.to_owned(),
+            id: json.get("id")?.as_str()?.to_owned(),
+            name: json.get("name")?.as_str()?.to_owned(),
+            url: json.get("url")?.as_str()?.to_owned(),
+        })
+    }
+}
+
+/// A piece of a message body, either plain text or an emote
+#[derive(Debug, Clone)]
+pub enum Token {
+    Text(String),
+    Emote(Emote),
+}
+
+/// A source of third-party emotes. The base URL of each provider can be changed through the
+/// `emotes` object of the config, e.g. `{ "emotes": { "bttv": "http://localhost:8080" } }`.
+pub trait EmoteProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn global(&self) -> Result<Vec<Emote>, Box<dyn std::error::Error>>;
+    fn channel(&self, channel_id: &str) -> Result<Vec<Emote>, Box<dyn std::error::Error>>;
+}
+
+fn base_url(provider: &str, default: &str) -> String {
+    crate::config::CONFIG
+        .get("emotes")
+        .and_then(|e| e.get(provider))
+        .and_then(|b| b.as_str())
+        .unwrap_or(default)
+        .trim_end_matches('/')
+        .to_owned()
+}
+
+fn get_json(url: &str) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    Ok(crate::common::CLIENT
+        .get(url)
+        .header("User-Agent", crate::common::USER_AGENT)
+        .send()?
+        .error_for_status()?
+        .json()?)
+}
+
+/// Some providers give protocol-relative links
+fn absolute(url: &str) -> String {
+    match url.strip_prefix("//") {
+        Some(url) => format!("https://{}", url),
+        None => url.to_owned(),
+    }
+}
+
+pub struct Bttv {
+    base: String,
+}
+
+impl Bttv {
+    pub fn new<S>(base: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self { base: base.into() }
+    }
+
+    fn parse(emotes: &serde_json::Value) -> Vec<Emote> {
+        emotes
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|emote| {
+                let id = emote.get("id")?.as_str()?;
+                Some(Emote {
+                    provider: String::from("bttv"),
+                    id: id.to_owned(),
+                    name: emote.get("code")?.as_str()?.to_owned(),
+                    url: format!("https://cdn.betterttv.net/emote/{}/1x", id),
+                })
+            })
+            .collect()
+    }
+}
+
+impl EmoteProvider for Bttv {
+    fn name(&self) -> &'static str {
+        "bttv"
+    }
+
+    fn global(&self) -> Result<Vec<Emote>, Box<dyn std::error::Error>> {
+        let json = get_json(&format!("{}/cached/emotes/global", self.base))?;
+        Ok(Self::parse(&json))
+    }
+
+    fn channel(&self, channel_id: &str) -> Result<Vec<Emote>, Box<dyn std::error::Error>> {
+        let json = get_json(&format!("{}/cached/users/twitch/{}", self.base, channel_id))?;
+        let mut emotes = Self::parse(json.get("channelEmotes").ok_or("Missing channelEmotes")?);
+        emotes.extend(Self::parse(
+            json.get("sharedEmotes").ok_or("Missing sharedEmotes")?,
+        ));
+        Ok(emotes)
+    }
+}
+
+pub struct Ffz {
+    base: String,
+}
+
+impl Ffz {
+    pub fn new<S>(base: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self { base: base.into() }
+    }
+
+    fn parse(sets: &serde_json::Value) -> Vec<Emote> {
+        sets.as_object()
+            .into_iter()
+            .flat_map(|sets| sets.values())
+            .flat_map(|set| set.get("emoticons").and_then(|e| e.as_array()))
+            .flatten()
+            .filter_map(|emote| {
+                let urls = emote.get("urls")?;
+                let url = urls
+                    .get("1")
+                    .or_else(|| urls.as_object()?.values().next())?
+                    .as_str()?;
+                Some(Emote {
+                    provider: String::from("ffz"),
+                    id: emote.get("id")?.to_string(),
+                    name: emote.get("name")?.as_str()?.to_owned(),
+                    url: absolute(url),
+                })
+            })
+            .collect()
+    }
+}
+
+impl EmoteProvider for Ffz {
+    fn name(&self) -> &'static str {
+        "ffz"
+    }
+
+    fn global(&self) -> Result<Vec<Emote>, Box<dyn std::error::Error>> {
+        let json = get_json(&format!("{}/set/global", self.base))?;
+        // Only the default sets are shown to everyone, the rest are limited to some users
+        let default_sets: Vec<String> = json
+            .get("default_sets")
+            .and_then(|d| d.as_array())
+            .into_iter()
+            .flatten()
+            .map(|id| id.to_string())
+            .collect();
+        let sets: serde_json::Map<String, serde_json::Value> = json
+            .get("sets")
+            .and_then(|s| s.as_object())
+            .ok_or("Missing sets")?
+            .iter()
+            .filter(|(id, _)| default_sets.contains(id))
+            .map(|(id, set)| (id.to_owned(), set.to_owned()))
+            .collect();
+        Ok(Self::parse(&serde_json::Value::Object(sets)))
+    }
+
+    fn channel(&self, channel_id: &str) -> Result<Vec<Emote>, Box<dyn std::error::Error>> {
+        let json = get_json(&format!("{}/room/id/{}", self.base, channel_id))?;
+        Ok(Self::parse(json.get("sets").ok_or("Missing sets")?))
+    }
+}
+
+pub struct SevenTv {
+    base: String,
+}
+
+impl SevenTv {
+    pub fn new<S>(base: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self { base: base.into() }
+    }
+
+    fn parse(set: &serde_json::Value) -> Vec<Emote> {
+        set.get("emotes")
+            .and_then(|e| e.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|emote| {
+                let host = emote.get("data")?.get("host")?.get("url")?.as_str()?;
+                Some(Emote {
+                    provider: String::from("7tv"),
+                    id: emote.get("id")?.as_str()?.to_owned(),
+                    name: emote.get("name")?.as_str()?.to_owned(),
+                    url: format!("{}/1x.webp", absolute(host)),
+                })
+            })
+            .collect()
+    }
+}
+
+impl EmoteProvider for SevenTv {
+    fn name(&self) -> &'static str {
+        "7tv"
+    }
+
+    fn global(&self) -> Result<Vec<Emote>, Box<dyn std::error::Error>> {
+        let json = get_json(&format!("{}/emote-sets/global", self.base))?;
+        Ok(Self::parse(&json))
+    }
+
+    fn channel(&self, channel_id: &str) -> Result<Vec<Emote>, Box<dyn std::error::Error>> {
+        let json = get_json(&format!("{}/users/twitch/{}", self.base, channel_id))?;
+        Ok(Self::parse(
+            json.get("emote_set").ok_or("Missing emote_set")?,
+        ))
+    }
+}
+
+/// The third-party providers, with any base URLs from the config
+pub fn providers() -> Vec<Box<dyn EmoteProvider>> {
+    vec![
+        Box::new(Bttv::new(base_url("bttv", BTTV_API))),
+        Box::new(Ffz::new(base_url("ffz", FFZ_API))),
+        Box::new(SevenTv::new(base_url("seventv", SEVENTV_API))),
+    ]
+}
+
+/// Emote sets saved under the data directory, so that each is only downloaded once a day
+pub struct Cache {
+    dir: Option<std::path::PathBuf>,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self {
+            dir: crate::config::data_dir().map(|dir| dir.join("emotes")),
+        }
+    }
+
+    fn path(&self, provider: &str, set: &str) -> Option<std::path::PathBuf> {
+        Some(
+            self.dir
+                .as_ref()?
+                .join(format!("{}-{}.json", provider, set)),
+        )
+    }
+
+    fn read(path: &std::path::Path) -> Result<Vec<Emote>, Box<dyn std::error::Error>> {
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        Ok(json
+            .as_array()
+            .ok_or("Could not convert cache -> array")?
+            .iter()
+            .filter_map(Emote::from_json)
+            .collect())
+    }
+
+    fn write(path: &std::path::Path, emotes: &[Emote]) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json: Vec<serde_json::Value> = emotes.iter().map(Emote::to_json).collect();
+        std::fs::write(path, serde_json::Value::Array(json).to_string())?;
+        Ok(())
+    }
+
+    /// The cached emote set if it is fresh, otherwise the result of `fetch`. An outdated set is
+    /// still used if it can't be fetched again.
+    pub fn get<F>(
+        &self,
+        provider: &str,
+        set: &str,
+        fetch: F,
+    ) -> Result<Vec<Emote>, Box<dyn std::error::Error>>
+    where
+        F: FnOnce() -> Result<Vec<Emote>, Box<dyn std::error::Error>>,
+    {
+        let path = match self.path(provider, set) {
+            Some(path) => path,
+            None => return fetch(),
+        };
+        let fresh = std::fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age < CACHE_TTL);
+        if fresh {
+            if let Ok(emotes) = Self::read(&path) {
+                return Ok(emotes);
+            }
+        }
+
+        match fetch() {
+            Ok(emotes) => {
+                if let Err(e) = Self::write(&path, &emotes) {
+                    eprintln!("Unable to cache {} emotes: {}", provider, e);
+                }
+                Ok(emotes)
+            }
+            Err(e) => Self::read(&path).map_err(|_| e),
+        }
+    }
+}
+
+/// Every emote that can be used in a channel's chat, by name
+pub struct Catalog {
+    emotes: std::collections::HashMap<String, Emote>,
+}
+
+impl Catalog {
+    /// Loads the global and channel emotes of every provider. Channel emotes take precedence over
+    /// global ones, and later providers over earlier ones.
+    pub fn load(channel_id: &str, providers: &[Box<dyn EmoteProvider>], cache: &Cache) -> Self {
+        let mut emotes = std::collections::HashMap::new();
+        for (set, channel) in [("global", None), (channel_id, Some(channel_id))] {
+            for provider in providers {
+                let result = cache.get(provider.name(), set, || match channel {
+                    Some(channel) => provider.channel(channel),
+                    None => provider.global(),
+                });
+                match result {
+                    Ok(set) => emotes.extend(set.into_iter().map(|e| (e.name.to_owned(), e))),
+                    Err(e) => eprintln!("Unable to load {} emotes: {}", provider.name(), e),
+                }
+            }
+        }
+        Self { emotes }
+    }
+
+    pub fn emotes(&self) -> impl Iterator<Item = &Emote> {
+        self.emotes.values()
+    }
+
+    /// Splits the body of a message into text and emotes. Twitch emotes come from the positions
+    /// recorded on the message, third-party emotes are matched by word.
+    pub fn tokens(&self, message: &crate::common::Message) -> Vec<Token> {
+        let chars: Vec<char> = message.body.chars().collect();
+        let mut tokens = Vec::new();
+        let mut position = 0;
+        for emote in &message.emotes {
+            if emote.start < position || emote.end >= chars.len() {
+                continue;
+            }
+            let text: String = chars[position..emote.start].iter().collect();
+            self.tokenize_text(&text, &mut tokens);
+            tokens.push(Token::Emote(Emote {
+                provider: String::from("twitch"),
+                id: emote.id.to_owned(),
+                name: chars[emote.start..=emote.end].iter().collect(),
+                url: format!("{}/{}/default/dark/1.0", TWITCH_CDN, emote.id),
+            }));
+            position = emote.end + 1;
+        }
+        let text: String = chars[position..].iter().collect();
+        self.tokenize_text(&text, &mut tokens);
+        tokens
+    }
+
+    fn tokenize_text(&self, text: &str, tokens: &mut Vec<Token>) {
+        let mut pending = String::new();
+        for piece in text.split_inclusive(char::is_whitespace) {
+            let word = piece.trim_end();
+            match self.emotes.get(word) {
+                Some(emote) => {
+                    if !pending.is_empty() {
+                        tokens.push(Token::Text(std::mem::take(&mut pending)));
+                    }
+                    tokens.push(Token::Emote(emote.to_owned()));
+                    pending.push_str(&piece[word.len()..]);
+                }
+                None => pending.push_str(piece),
+            }
+        }
+        if !pending.is_empty() {
+            tokens.push(Token::Text(pending));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Write};
+
+    /// A local stand-in for the API of a provider, answering each path with its JSON body or a
+    /// 404 for any other path
+    fn stand_in(routes: Vec<(&'static str, serde_json::Value)>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut line = String::new();
+                while line != "\r\n" {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                }
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let response = match routes.iter().find(|(route, _)| *route == path) {
+                    Some((_, body)) => {
+                        let body = body.to_string();
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        )
+                    }
+                    None => String::from(
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    ),
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    fn names(emotes: &[Emote]) -> Vec<&str> {
+        emotes.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    fn emote(provider: &str, name: &str) -> Emote {
+        Emote {
+            provider: provider.to_owned(),
+            id: format!("{}-{}", provider, name),
+            name: name.to_owned(),
+            url: String::new(),
+        }
+    }
+
+    /// A unique, empty directory for a cache
+    fn cache_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat_reader-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn bttv_from_stand_in() {
+        let base = stand_in(vec![
+            (
+                "/cached/emotes/global",
+                serde_json::json!([{ "id": "1", "code": "OMEGALUL" }]),
+            ),
+            (
+                "/cached/users/twitch/12",
+                serde_json::json!({
+                    "channelEmotes": [{ "id": "2", "code": "catJAM" }],
+                    "sharedEmotes": [{ "id": "3", "code": "monkaS" }]
+                }),
+            ),
+        ]);
+        let bttv = Bttv::new(base);
+        let global = bttv.global().unwrap();
+        assert_eq!(names(&global), ["OMEGALUL"]);
+        assert_eq!(global[0].url, "https://cdn.betterttv.net/emote/1/1x");
+        assert_eq!(names(&bttv.channel("12").unwrap()), ["catJAM", "monkaS"]);
+        assert!(bttv.channel("34").is_err());
+    }
+
+    #[test]
+    fn ffz_from_stand_in() {
+        let base = stand_in(vec![
+            (
+                "/set/global",
+                serde_json::json!({
+                    "default_sets": [3],
+                    "sets": {
+                        "3": { "emoticons": [{ "id": 25, "name": "ZreknarF", "urls": { "1": "//cdn.frankerfacez.com/emote/25/1" } }] },
+                        "4": { "emoticons": [{ "id": 26, "name": "Limited", "urls": { "1": "//cdn.frankerfacez.com/emote/26/1" } }] }
+                    }
+                }),
+            ),
+            (
+                "/room/id/12",
+                serde_json::json!({
+                    "sets": { "5": { "emoticons": [{ "id": 27, "name": "OhISee", "urls": { "2": "https://cdn.frankerfacez.com/emote/27/2" } }] } }
+                }),
+            ),
+        ]);
+        let ffz = Ffz::new(base);
+        let global = ffz.global().unwrap();
+        assert_eq!(names(&global), ["ZreknarF"]);
+        assert_eq!(global[0].id, "25");
+        assert_eq!(global[0].url, "https://cdn.frankerfacez.com/emote/25/1");
+        let channel = ffz.channel("12").unwrap();
+        assert_eq!(names(&channel), ["OhISee"]);
+        assert_eq!(channel[0].url, "https://cdn.frankerfacez.com/emote/27/2");
+    }
+
+    #[test]
+    fn seventv_from_stand_in() {
+        let emote = |id: &str, name: &str| serde_json::json!({ "id": id, "name": name, "data": { "host": { "url": format!("//cdn.7tv.app/emote/{}", id) } } });
+        let base = stand_in(vec![
+            (
+                "/emote-sets/global",
+                serde_json::json!({ "emotes": [emote("a", "EZ")] }),
+            ),
+            (
+                "/users/twitch/12",
+                serde_json::json!({ "emote_set": { "emotes": [emote("b", "peepoHappy")] } }),
+            ),
+        ]);
+        let seventv = SevenTv::new(base);
+        let global = seventv.global().unwrap();
+        assert_eq!(names(&global), ["EZ"]);
+        assert_eq!(global[0].url, "https://cdn.7tv.app/emote/a/1x.webp");
+        assert_eq!(names(&seventv.channel("12").unwrap()), ["peepoHappy"]);
+    }
+
+    #[test]
+    fn cache_is_fresh_for_a_day() {
+        let dir = cache_dir("fresh");
+        let cache = Cache {
+            dir: Some(dir.to_owned()),
+        };
+        let first = cache
+            .get("bttv", "global", || Ok(vec![emote("bttv", "first")]))
+            .unwrap();
+        assert_eq!(names(&first), ["first"]);
+
+        // A fresh set is read back without fetching
+        let cached = cache
+            .get("bttv", "global", || panic!("fetched a fresh set"))
+            .unwrap();
+        assert_eq!(names(&cached), ["first"]);
+
+        // A stale set is fetched again
+        let path = cache.path("bttv", "global").unwrap();
+        let stale = std::time::SystemTime::now() - CACHE_TTL - std::time::Duration::from_secs(60);
+        let age = |path: &std::path::Path| {
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(stale)
+                .unwrap()
+        };
+        age(&path);
+        let second = cache
+            .get("bttv", "global", || Ok(vec![emote("bttv", "second")]))
+            .unwrap();
+        assert_eq!(names(&second), ["second"]);
+
+        // Unless it can't be, when the stale set is still used
+        age(&path);
+        let fallback = cache
+            .get("bttv", "global", || Err("offline".into()))
+            .unwrap();
+        assert_eq!(names(&fallback), ["second"]);
+        assert!(cache.get("bttv", "12", || Err("offline".into())).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A provider with fixed emote sets, failing when it has no channel set
+    struct Stub {
+        name: &'static str,
+        global: Vec<&'static str>,
+        channel: Option<Vec<&'static str>>,
+    }
+
+    impl EmoteProvider for Stub {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn global(&self) -> Result<Vec<Emote>, Box<dyn std::error::Error>> {
+            Ok(self.global.iter().map(|n| emote(self.name, n)).collect())
+        }
+
+        fn channel(&self, _: &str) -> Result<Vec<Emote>, Box<dyn std::error::Error>> {
+            let channel = self.channel.as_ref().ok_or("No channel emotes")?;
+            Ok(channel.iter().map(|n| emote(self.name, n)).collect())
+        }
+    }
+
+    #[test]
+    fn catalog_precedence() {
+        let providers: Vec<Box<dyn EmoteProvider>> = vec![
+            Box::new(Stub {
+                name: "bttv",
+                global: vec!["Shared", "Global", "Both"],
+                channel: Some(vec!["Channel"]),
+            }),
+            Box::new(Stub {
+                name: "ffz",
+                global: vec!["Shared"],
+                channel: None,
+            }),
+            Box::new(Stub {
+                name: "7tv",
+                global: vec!["Channel"],
+                channel: Some(vec!["Both"]),
+            }),
+        ];
+        let catalog = Catalog::load("12", &providers, &Cache { dir: None });
+        let provider = |name: &str| catalog.emotes.get(name).map(|e| e.provider.as_str());
+
+        // Later providers replace earlier ones
+        assert_eq!(provider("Shared"), Some("ffz"));
+        // Channel emotes replace global ones, even from an earlier provider
+        assert_eq!(provider("Channel"), Some("bttv"));
+        assert_eq!(provider("Both"), Some("7tv"));
+        assert_eq!(provider("Global"), Some("bttv"));
+        assert_eq!(catalog.emotes().count(), 4);
+    }
+
+    #[test]
+    fn tokenizes_emotes() {
+        let providers: Vec<Box<dyn EmoteProvider>> = vec![Box::new(Stub {
+            name: "bttv",
+            global: vec!["catJAM"],
+            channel: Some(Vec::new()),
+        })];
+        let catalog = Catalog::load("12", &providers, &Cache { dir: None });
+        let message = crate::common::Message {
+            body: String::from("Kappa catJAM  hi"),
+            emotes: vec![crate::common::Emote {
+                id: String::from("25"),
+                start: 0,
+                end: 4,
+            }],
+            ..Default::default()
+        };
+        let tokens: Vec<String> = catalog
+            .tokens(&message)
+            .into_iter()
+            .map(|token| match token {
+                Token::Text(text) => text,
+                Token::Emote(emote) => format!("[{}:{}]", emote.provider, emote.name),
+            })
+            .collect();
+        assert_eq!(tokens, ["[twitch:Kappa]", " ", "[bttv:catJAM]", "  hi"]);
+    }
+}
@@ -0,0 +1,730 @@
+use rayon::prelude::*;
+use std::io::Write;
+
+/// Quality directories that Twitch stores next to `chunked/` (the source quality) for a VOD
+pub const TWITCH_QUALITIES: [&str; 9] = [
+    "chunked",
+    "1080p60",
+    "1080p30",
+    "720p60",
+    "720p30",
+    "480p30",
+    "360p30",
+    "160p30",
+    "audio_only",
+];
+
+const SEGMENT_RETRIES: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: String,
+    pub bandwidth: Option<u64>,
+    pub resolution: Option<String>,
+    pub uri: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub uri: String,
+    pub duration: f64,
+    /// The initialization section from `#EXT-X-MAP` that has to come before the segment, as used
+    /// by fragmented MP4 playlists
+    pub map: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct MediaPlaylist {
+    pub target_duration: Option<f64>,
+    pub segments: Vec<Segment>,
+    pub ended: bool,
+}
+
+#[derive(Debug)]
+pub enum Playlist {
+    Master(Vec<Variant>),
+    Media(MediaPlaylist),
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}]", self.name)?;
+        if let Some(resolution) = &self.resolution {
+            write!(f, "[{}]", resolution)?;
+        }
+        if let Some(bandwidth) = self.bandwidth {
+            write!(f, "[{} kbps]", bandwidth / 1000)?;
+        }
+        write!(f, " {}", self.uri)
+    }
+}
+
+/// A segment that Twitch has replaced with a muted copy, along with the original segment when it
+/// is still available
+#[derive(Debug)]
+pub struct MutedSegment {
+    pub index: usize,
+    pub offset: f64,
+    pub duration: f64,
+    pub unmuted: Option<String>,
+}
+
+impl MediaPlaylist {
+    pub fn duration(&self) -> f64 {
+        self.segments.iter().map(|segment| segment.duration).sum()
+    }
+
+    /// Writes the playlist back out, with every segment pointing to an absolute URL
+    pub fn to_m3u8(&self, base: &str) -> String {
+        // EXT-X-MAP requires version 6
+        let version = if self.segments.iter().any(|segment| segment.map.is_some()) {
+            6
+        } else {
+            3
+        };
+        let mut out = format!("#EXTM3U\n#EXT-X-VERSION:{}\n", version);
+        if let Some(target) = self.target_duration {
+            out.push_str(&format!("#EXT-X-TARGETDURATION:{}\n", target.ceil() as u64));
+        }
+        out.push_str("#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-MEDIA-SEQUENCE:0\n");
+        let mut map = None;
+        for segment in &self.segments {
+            if segment.map.is_some() && segment.map != map {
+                map = segment.map.clone();
+                out.push_str(&format!(
+                    "#EXT-X-MAP:URI=\"{}\"\n",
+                    resolve(base, map.as_deref().unwrap_or_default())
+                ));
+            }
+            out.push_str(&format!(
+                "#EXTINF:{:.3},\n{}\n",
+                segment.duration,
+                resolve(base, &segment.uri)
+            ));
+        }
+        if self.ended {
+            out.push_str("#EXT-X-ENDLIST\n");
+        }
+        out
+    }
+
+    /// Finds the segments named `<n>-muted.ts` or `<n>-unmuted.ts`, checking whether `<n>.ts` can
+    /// still be retrieved when `probe` is set
+    pub fn muted_segments(&self, base: &str, probe: bool) -> Vec<MutedSegment> {
+        let mut offset = 0.0;
+        let muted: Vec<(usize, f64, &Segment, String)> = self
+            .segments
+            .iter()
+            .enumerate()
+            .filter_map(|(index, segment)| {
+                let start = offset;
+                offset += segment.duration;
+                Some((index, start, segment, original_segment(&segment.uri)?))
+            })
+            .collect();
+
+        muted
+            .into_par_iter()
+            .map(|(index, offset, segment, original)| {
+                let unmuted = if probe {
+                    crate::common::CLIENT
+                        .head(resolve(base, &original))
+                        .send()
+                        .is_ok_and(|response| response.status().is_success())
+                        .then_some(original)
+                } else {
+                    None
+                };
+                MutedSegment {
+                    index,
+                    offset,
+                    duration: segment.duration,
+                    unmuted,
+                }
+            })
+            .collect()
+    }
+
+    /// Swaps every muted segment for its original, where one was found
+    pub fn unmute(&mut self, muted: &[MutedSegment]) {
+        for segment in muted {
+            if let Some(unmuted) = &segment.unmuted {
+                self.segments[segment.index].uri = unmuted.to_owned();
+            }
+        }
+    }
+}
+
+/// The name of the original segment that a muted `<n>-muted.ts` or `<n>-unmuted.ts` replaced
+fn original_segment(uri: &str) -> Option<String> {
+    let number = uri
+        .strip_suffix("-unmuted.ts")
+        .or_else(|| uri.strip_suffix("-muted.ts"))?;
+    Some(format!("{}.ts", number))
+}
+
+/// Groups muted segments into runs of consecutive segments
+pub fn muted_ranges(muted: &[MutedSegment]) -> Vec<&[MutedSegment]> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for i in 1..=muted.len() {
+        if i == muted.len() || muted[i].index != muted[i - 1].index + 1 {
+            ranges.push(&muted[start..i]);
+            start = i;
+        }
+    }
+    ranges
+}
+
+/// Splits an attribute list such as `BANDWIDTH=1000,CODECS="a,b"` into its key/value pairs
+fn attributes(list: &str) -> std::collections::HashMap<&str, &str> {
+    let mut out = std::collections::HashMap::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in list
+        .char_indices()
+        .chain(std::iter::once((list.len(), ',')))
+    {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                if let Some((key, value)) = list[start..i].split_once('=') {
+                    out.insert(key.trim(), value.trim().trim_matches('"'));
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+pub fn parse(text: &str) -> Result<Playlist, Box<dyn std::error::Error>> {
+    let mut lines = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|l| !l.is_empty());
+    if lines.next() != Some("#EXTM3U") {
+        return Err("Missing #EXTM3U header".into());
+    }
+
+    let mut variants = Vec::new();
+    let mut media = MediaPlaylist::default();
+    let mut stream_info: Option<std::collections::HashMap<&str, &str>> = None;
+    let mut media_names = std::collections::HashMap::new();
+    let mut duration: Option<f64> = None;
+    let mut map: Option<String> = None;
+
+    for line in lines {
+        if let Some(info) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            stream_info = Some(attributes(info));
+        } else if let Some(info) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let info = attributes(info);
+            if let (Some(group), Some(name)) = (info.get("GROUP-ID"), info.get("NAME")) {
+                media_names.insert(group.to_string(), name.to_string());
+            }
+        } else if let Some(info) = line.strip_prefix("#EXTINF:") {
+            let seconds = info.split(',').next().unwrap_or_default();
+            duration = Some(seconds.parse()?);
+        } else if let Some(info) = line.strip_prefix("#EXT-X-MAP:") {
+            map = Some(
+                attributes(info)
+                    .get("URI")
+                    .ok_or("Missing URI in #EXT-X-MAP")?
+                    .to_string(),
+            );
+        } else if let Some(target) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            media.target_duration = Some(target.parse()?);
+        } else if line == "#EXT-X-ENDLIST" {
+            media.ended = true;
+        } else if line.starts_with('#') {
+            continue;
+        } else if let Some(info) = stream_info.take() {
+            let resolution = info.get("RESOLUTION").map(|r| r.to_string());
+            let name = info
+                .get("VIDEO")
+                .map(|group| {
+                    media_names
+                        .get(*group)
+                        .cloned()
+                        .unwrap_or_else(|| group.to_string())
+                })
+                .or_else(|| resolution.clone())
+                .unwrap_or_else(|| line.to_owned());
+            variants.push(Variant {
+                name,
+                bandwidth: info.get("BANDWIDTH").and_then(|b| b.parse().ok()),
+                resolution,
+                uri: line.to_owned(),
+            });
+        } else {
+            media.segments.push(Segment {
+                uri: line.to_owned(),
+                duration: duration.take().unwrap_or_default(),
+                map: map.clone(),
+            });
+        }
+    }
+
+    if variants.is_empty() {
+        Ok(Playlist::Media(media))
+    } else {
+        variants.sort_by_key(|variant| std::cmp::Reverse(variant.bandwidth));
+        Ok(Playlist::Master(variants))
+    }
+}
+
+/// Resolves a URI from a playlist against the URL of the playlist itself
+pub fn resolve(base: &str, uri: &str) -> String {
+    if uri.contains("://") {
+        return uri.to_owned();
+    }
+    let base = base.split(&['?', '#'][..]).next().unwrap_or_default();
+    if let Some(path) = uri.strip_prefix('/') {
+        let origin_end = base
+            .find("://")
+            .and_then(|scheme| base[scheme + 3..].find('/').map(|i| i + scheme + 3))
+            .unwrap_or(base.len());
+        format!("{}/{}", &base[..origin_end], path)
+    } else {
+        match base.rfind('/') {
+            Some(i) => format!("{}{}", &base[..=i], uri),
+            None => uri.to_owned(),
+        }
+    }
+}
+
+pub fn fetch(url: &str) -> Result<Playlist, Box<dyn std::error::Error>> {
+    let text = crate::common::CLIENT
+        .get(url)
+        .send()?
+        .error_for_status()?
+        .text()?;
+    parse(&text)
+}
+
+/// Fetches a media playlist, following a master playlist to the variant matching `quality`, or
+/// the highest bandwidth variant when no quality is given. Returns the URL of the media playlist
+/// along with the playlist.
+pub fn fetch_media(
+    url: &str,
+    quality: Option<&str>,
+) -> Result<(String, MediaPlaylist), Box<dyn std::error::Error>> {
+    match fetch(url)? {
+        Playlist::Media(media) => Ok((url.to_owned(), media)),
+        Playlist::Master(variants) => {
+            let variant = match quality {
+                Some(quality) => variants.iter().find(|variant| {
+                    variant.name == quality || variant.resolution.as_deref() == Some(quality)
+                }),
+                None => variants.first(),
+            }
+            .ok_or("No matching variant in master playlist")?;
+            let variant_url = resolve(url, &variant.uri);
+            match fetch(&variant_url)? {
+                Playlist::Media(media) => Ok((variant_url, media)),
+                Playlist::Master(_) => Err("Variant is not a media playlist".into()),
+            }
+        }
+    }
+}
+
+/// Swaps the quality directory of a Twitch VOD playlist URL, such as `chunked/` for `720p60/`
+pub fn with_quality(url: &str, quality: &str) -> Option<String> {
+    let (prefix, file) = url.rsplit_once('/')?;
+    let (base, _) = prefix.rsplit_once('/')?;
+    Some(format!("{}/{}/{}", base, quality, file))
+}
+
+/// Lists the Twitch quality directories that exist for a VOD playlist URL, best first
+pub fn probe_variants(url: &str) -> Vec<String> {
+    TWITCH_QUALITIES
+        .par_iter()
+        .filter(|quality| {
+            with_quality(url, quality).is_some_and(|link| {
+                crate::common::CLIENT
+                    .head(link)
+                    .send()
+                    .is_ok_and(|response| response.status().is_success())
+            })
+        })
+        .map(|quality| quality.to_string())
+        .collect()
+}
+
+fn download_segment(url: &str, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    // Segments are only moved into place once complete, so any existing file can be kept when
+    // resuming
+    if path.exists() {
+        return Ok(());
+    }
+
+    let response = crate::common::CLIENT.get(url).send()?.error_for_status()?;
+    let expected = response.content_length();
+    let bytes = response.bytes()?;
+    if let Some(expected) = expected {
+        if bytes.len() as u64 != expected {
+            return Err(format!("Expected {} bytes, received {}", expected, bytes.len()).into());
+        }
+    }
+    if url.split('?').next().unwrap_or_default().ends_with(".ts") && bytes.first() != Some(&0x47) {
+        return Err("Segment is not a valid MPEG-TS stream".into());
+    }
+
+    let part = path.with_extension("part");
+    std::fs::write(&part, &bytes)?;
+    std::fs::rename(part, path)?;
+    Ok(())
+}
+
+/// Downloads every segment of a media playlist with `concurrency` parallel requests and
+/// concatenates them into `output`. Segments are kept in a `.parts` directory next to the output
+/// until the download is complete, so an interrupted download resumes where it left off.
+pub fn download(
+    url: &str,
+    media: &MediaPlaylist,
+    output: &std::path::Path,
+    concurrency: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let parts = output.with_extension("parts");
+    std::fs::create_dir_all(&parts)?;
+
+    // Initialization sections are downloaded once each, and written ahead of the first segment
+    // that uses them
+    let mut maps: Vec<&str> = Vec::new();
+    for map in media
+        .segments
+        .iter()
+        .flat_map(|segment| segment.map.as_deref())
+    {
+        if !maps.contains(&map) {
+            maps.push(map);
+        }
+    }
+    let files: Vec<(&str, std::path::PathBuf)> = maps
+        .iter()
+        .enumerate()
+        .map(|(i, map)| (*map, parts.join(format!("map{:03}.mp4", i))))
+        .chain(
+            media
+                .segments
+                .iter()
+                .enumerate()
+                .map(|(i, segment)| (segment.uri.as_str(), parts.join(format!("{:06}.ts", i)))),
+        )
+        .collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(concurrency)
+        .build()?;
+    let failures: Vec<String> = pool.install(|| {
+        files
+            .par_iter()
+            .filter_map(|(uri, path)| {
+                let link = resolve(url, uri);
+                let mut result = download_segment(&link, path);
+                for _ in 1..SEGMENT_RETRIES {
+                    if result.is_ok() {
+                        break;
+                    }
+                    result = download_segment(&link, path);
+                }
+                result.err().map(|e| format!("{}: {}", link, e))
+            })
+            .collect()
+    });
+    if !failures.is_empty() {
+        return Err(format!(
+            "{} of {} segments failed, run again to resume\n{}",
+            failures.len(),
+            files.len(),
+            failures.join("\n")
+        )
+        .into());
+    }
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(output)?);
+    let mut map = None;
+    for (i, segment) in media.segments.iter().enumerate() {
+        if segment.map.is_some() && segment.map != map {
+            map = segment.map.clone();
+            let index = maps.iter().position(|m| Some(*m) == map.as_deref());
+            let path = parts.join(format!("map{:03}.mp4", index.unwrap_or_default()));
+            std::io::copy(&mut std::fs::File::open(path)?, &mut file)?;
+        }
+        let mut segment = std::fs::File::open(parts.join(format!("{:06}.ts", i)))?;
+        std::io::copy(&mut segment, &mut file)?;
+    }
+    file.flush()?;
+    std::fs::remove_dir_all(parts)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Write};
+
+    fn media(text: &str) -> MediaPlaylist {
+        match parse(text).unwrap() {
+            Playlist::Media(media) => media,
+            Playlist::Master(_) => panic!("Expected a media playlist"),
+        }
+    }
+
+    #[test]
+    fn parses_master_playlist() {
+        let variants = match parse(include_str!("../tests/fixtures/master.m3u8")).unwrap() {
+            Playlist::Master(variants) => variants,
+            Playlist::Media(_) => panic!("Expected a master playlist"),
+        };
+        let names: Vec<&str> = variants.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["1080p60", "720p60", "Audio Only"]);
+        assert_eq!(variants[0].bandwidth, Some(6667738));
+        assert_eq!(variants[0].resolution.as_deref(), Some("1920x1080"));
+        assert_eq!(variants[2].resolution, None);
+        assert_eq!(
+            resolve(
+                "https://d1m7jfoe9zdc1j.cloudfront.net/abc_channel_1_2/chunked/index-dvr.m3u8",
+                &variants[2].uri
+            ),
+            "https://d1m7jfoe9zdc1j.cloudfront.net/abc_channel_1_2/chunked/../audio_only/index-dvr.m3u8"
+        );
+    }
+
+    #[test]
+    fn parses_media_playlist() {
+        let media = media(include_str!("../tests/fixtures/media.m3u8"));
+        assert_eq!(media.target_duration, Some(10.0));
+        assert!(media.ended);
+        assert_eq!(media.segments.len(), 5);
+        assert_eq!(media.duration(), 45.5);
+        assert!(media.segments.iter().all(|segment| segment.map.is_none()));
+
+        let muted = media.muted_segments("https://example.com/vod/index-dvr.m3u8", false);
+        let offsets: Vec<(usize, f64)> = muted.iter().map(|m| (m.index, m.offset)).collect();
+        assert_eq!(offsets, [(1, 10.0), (2, 20.0), (4, 40.0)]);
+        assert_eq!(original_segment("1-muted.ts").as_deref(), Some("1.ts"));
+        assert_eq!(original_segment("4-unmuted.ts").as_deref(), Some("4.ts"));
+        assert_eq!(original_segment("5-muted.ts.part"), None);
+        assert_eq!(original_segment("6-muted-intro.ts"), None);
+        assert_eq!(original_segment("7.ts"), None);
+        let ranges: Vec<usize> = muted_ranges(&muted).iter().map(|r| r.len()).collect();
+        assert_eq!(ranges, [2, 1]);
+    }
+
+    #[test]
+    fn unmutes_and_writes_playlist() {
+        let mut media = media(include_str!("../tests/fixtures/media.m3u8"));
+        let mut muted = media.muted_segments("https://example.com/vod/index-dvr.m3u8", false);
+        muted[0].unmuted = Some(String::from("1.ts"));
+        media.unmute(&muted);
+
+        let written = media.to_m3u8("https://example.com/vod/index-dvr.m3u8");
+        assert!(written.starts_with("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:10\n"));
+        assert!(written.contains("#EXTINF:10.000,\nhttps://example.com/vod/1.ts\n"));
+        assert!(written.contains("https://example.com/vod/2-muted.ts\n"));
+        assert!(written.ends_with("#EXT-X-ENDLIST\n"));
+
+        let reparsed = self::media(&written);
+        assert_eq!(reparsed.segments.len(), 5);
+        assert_eq!(reparsed.duration(), 45.5);
+    }
+
+    #[test]
+    fn parses_fmp4_playlist() {
+        let media = media(include_str!("../tests/fixtures/fmp4.m3u8"));
+        let maps: Vec<Option<&str>> = media.segments.iter().map(|s| s.map.as_deref()).collect();
+        assert_eq!(
+            maps,
+            [
+                Some("init-0.mp4"),
+                Some("init-0.mp4"),
+                Some("/vod/init-1.mp4")
+            ]
+        );
+
+        let written = media.to_m3u8("https://example.com/vod/index.m3u8");
+        assert!(written.starts_with("#EXTM3U\n#EXT-X-VERSION:6\n"));
+        assert_eq!(written.matches("#EXT-X-MAP").count(), 2);
+        assert!(written.contains(
+            "#EXT-X-MAP:URI=\"https://example.com/vod/init-0.mp4\"\n#EXTINF:10.000,\n\
+             https://example.com/vod/0.mp4\n"
+        ));
+        assert!(written.contains("#EXT-X-MAP:URI=\"https://example.com/vod/init-1.mp4\"\n"));
+    }
+
+    #[test]
+    fn rejects_invalid_playlists() {
+        assert!(parse("#EXTINF:10,\n0.ts").is_err());
+        assert!(parse("#EXTM3U\n#EXT-X-MAP:BYTERANGE=\"1@0\"\n").is_err());
+    }
+
+    /// Serves HTTP on a local port until the test ends, answering each request path with the body
+    /// from `respond` and the Content-Length that it claims, or a 404. Returns the address of the
+    /// server along with every path requested so far.
+    fn serve<F>(respond: F) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>)
+    where
+        F: Fn(&str) -> Option<(Vec<u8>, usize)> + Send + 'static,
+    {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let requested = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                std::io::BufReader::new(&stream)
+                    .read_line(&mut request)
+                    .unwrap();
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                requested.lock().unwrap().push(path.to_owned());
+                let (status, body, length) = match respond(path) {
+                    Some((body, length)) => ("200 OK", body, length),
+                    None => ("404 Not Found", Vec::new(), 0),
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status, length
+                )
+                .and_then(|_| stream.write_all(&body));
+            }
+        });
+        (address, requests)
+    }
+
+    /// Serves the fixture playlists under `/vod/`, with each segment an MPEG-TS sync byte (`G`)
+    /// followed by its name
+    fn serve_vod() -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        serve(|path| {
+            let body = match path {
+                "/vod/chunked/index-dvr.m3u8" => {
+                    include_str!("../tests/fixtures/master.m3u8").to_owned()
+                }
+                "/vod/audio_only/index-dvr.m3u8" => {
+                    include_str!("../tests/fixtures/media.m3u8").to_owned()
+                }
+                path => format!(
+                    "G{};",
+                    path.strip_prefix("/vod/audio_only/")?.strip_suffix(".ts")?
+                ),
+            };
+            Some((body.clone().into_bytes(), body.len()))
+        })
+    }
+
+    /// A new, empty directory for files written by a test
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("chat_reader_hls_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn downloads_from_master_playlist() {
+        let (address, _) = serve_vod();
+        let master = format!("{}/vod/chunked/index-dvr.m3u8", address);
+        let (url, media) = fetch_media(&master, Some("Audio Only")).unwrap();
+        assert_eq!(
+            url,
+            format!("{}/vod/chunked/../audio_only/index-dvr.m3u8", address)
+        );
+        assert!(fetch_media(&master, Some("480p30")).is_err());
+
+        let dir = temp_dir("master");
+        let output = dir.join("vod.ts");
+        download(&url, &media, &output, 2).unwrap();
+        let written = std::fs::read_to_string(&output).unwrap();
+        assert!(!dir.join("vod.parts").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(written, "G0;G1-muted;G2-muted;G3;G4-unmuted;");
+    }
+
+    #[test]
+    fn resumes_partial_downloads() {
+        let (address, requests) = serve_vod();
+        let url = format!("{}/vod/audio_only/index-dvr.m3u8", address);
+        let dir = temp_dir("resume");
+        let output = dir.join("vod.ts");
+        let parts = dir.join("vod.parts");
+        std::fs::create_dir_all(&parts).unwrap();
+        std::fs::write(parts.join("000001.ts"), "Gkept;").unwrap();
+        std::fs::write(parts.join("000002.part"), "G2-mu").unwrap();
+
+        download(
+            &url,
+            &media(include_str!("../tests/fixtures/media.m3u8")),
+            &output,
+            2,
+        )
+        .unwrap();
+        let written = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(written, "G0;Gkept;G2-muted;G3;G4-unmuted;");
+        let mut requested = requests.lock().unwrap().clone();
+        requested.sort();
+        assert_eq!(
+            requested,
+            [
+                "/vod/audio_only/0.ts",
+                "/vod/audio_only/2-muted.ts",
+                "/vod/audio_only/3.ts",
+                "/vod/audio_only/4-unmuted.ts",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_and_invalid_segments() {
+        let (address, _) = serve(|path| match path {
+            "/short.ts" => Some((b"G0;".to_vec(), 10)),
+            "/page.ts" | "/init.mp4" => Some((b"<html>".to_vec(), 6)),
+            _ => None,
+        });
+        let dir = temp_dir("invalid");
+        let path = dir.join("000000.ts");
+
+        assert!(download_segment(&format!("{}/short.ts", address), &path).is_err());
+        assert!(!path.exists());
+        let error = download_segment(&format!("{}/page.ts", address), &path).unwrap_err();
+        assert_eq!(error.to_string(), "Segment is not a valid MPEG-TS stream");
+        assert!(!path.exists());
+        assert!(download_segment(&format!("{}/missing.ts", address), &path).is_err());
+        assert!(!path.exists());
+
+        // Only transport stream segments start with a sync byte
+        download_segment(&format!("{}/init.mp4", address), &path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "<html>");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn downloads_fmp4_with_init_sections() {
+        let (address, _) = serve(|path| {
+            let body = path
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .replace(".mp4", ";");
+            Some((body.clone().into_bytes(), body.len()))
+        });
+        let url = format!("{}/vod/index.m3u8", address);
+
+        let dir = temp_dir("fmp4");
+        let output = dir.join("vod.mp4");
+        download(
+            &url,
+            &media(include_str!("../tests/fixtures/fmp4.m3u8")),
+            &output,
+            2,
+        )
+        .unwrap();
+        let written = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(written, "init-0;0;1;init-1;2;");
+    }
+}
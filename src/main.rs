@@ -10,6 +10,36 @@ mod twitch;
 #[path = "twitchrecover.rs"]
 mod twitchrecover;
 
+#[path = "twitchlive.rs"]
+mod twitchlive;
+
+#[path = "archive.rs"]
+mod archive;
+
+#[path = "config.rs"]
+mod config;
+
+#[path = "rules.rs"]
+mod rules;
+
+#[path = "watch.rs"]
+mod watch;
+
+#[path = "hls.rs"]
+mod hls;
+
+#[path = "emotes.rs"]
+mod emotes;
+
+#[path = "render.rs"]
+mod render;
+
+#[path = "replay.rs"]
+mod replay;
+
+#[path = "tui.rs"]
+mod tui;
+
 #[path = "tiktok.rs"]
 mod tiktok;
 
@@ -19,6 +49,7 @@ mod whisper;
 use crate::common::Vod;
 use clap::{Parser, Subcommand};
 use colored::Colorize;
+use hhmmss::Hhmmss;
 
 #[derive(Parser)]
 #[clap(arg_required_else_help(true))]
@@ -29,6 +60,20 @@ struct Args {
     /// Filter chat search results
     #[clap(short, long, value_parser, default_value = "")]
     filter: regex::Regex,
+
+    /// Directory to record chat into, as one JSON message per line. Records live chat, watched
+    /// VODs, the VODs of channel listings and recovered VODs
+    #[clap(short, long)]
+    archive: Option<std::path::PathBuf>,
+
+    /// Run the alert rules from the config file over all chat that is read
+    #[clap(long, parse(from_flag))]
+    alerts: bool,
+
+    /// Draw emotes and badges inline in Twitch chat with the kitty graphics protocol or sixel,
+    /// falling back to colored text. Supported by twitch vod, twitch live and replay twitch
+    #[clap(long, parse(from_flag))]
+    images: bool,
 }
 
 #[derive(clap::Args)]
@@ -53,10 +98,31 @@ struct TwitchChannelOpts {
     #[clap(short, long, parse(from_flag))]
     vods: bool,
 
-    /// Attempt to recover as many (vod-pointing) m3u8 links (upto 365 days prior) from a channel
+    /// Attempt to recover as many (vod-pointing) m3u8 links (upto 365 days prior by default) from a
+    /// channel
     #[clap(short, long, parse(from_flag))]
     recover: bool,
 
+    /// Where to find the stream history used for recovery: sullygnome (or sullygnome:DAYS to go
+    /// back other than 365 days), twitchtracker (only its first page of recent streams), or a
+    /// CSV/JSON file of stream IDs and start times. Can be given multiple times, defaults to
+    /// sullygnome
+    #[clap(long)]
+    history: Vec<String>,
+
+    /// Only search VOD chat sent during chapters (games or categories) matching this pattern
+    #[clap(long, value_parser)]
+    chapter: Option<regex::Regex>,
+
+    /// Only show messages of these kinds: an event such as sub, giftsub, raid or deleted, a reply
+    /// or a cheer
+    #[clap(long)]
+    event: Vec<String>,
+
+    /// Print the VOD chat from this many seconds before each matching clip until the clip ends
+    #[clap(long)]
+    clip_context: Option<f64>,
+
     #[clap(flatten)]
     transcribeopts: TranscribeOpts,
 
@@ -64,9 +130,54 @@ struct TwitchChannelOpts {
     showall: bool,
 }
 
+#[derive(clap::Args)]
+struct StreamOpts {
+    /// Only include streams broadcasting in these languages, such as "en"
+    #[clap(long)]
+    stream_language: Vec<String>,
+
+    /// Order of the live streams, either RELEVANCE, VIEWERS or RECENT
+    #[clap(long, default_value = "RELEVANCE")]
+    stream_sort: crate::twitch::StreamSort,
+
+    /// Only include streams with this freeform tag, can be given multiple times
+    #[clap(long)]
+    tag: Vec<String>,
+}
+
+impl StreamOpts {
+    fn filter(&self) -> crate::twitch::StreamFilter {
+        crate::twitch::StreamFilter {
+            languages: self.stream_language.clone(),
+            sort: self.stream_sort,
+            tags: self.tag.clone(),
+        }
+    }
+}
+
 #[derive(Subcommand)]
-enum DirectoryClips {
-    DirectoryClips { recency: crate::twitch::Recency },
+enum DirectoryListing {
+    DirectoryClips {
+        recency: crate::twitch::Recency,
+    },
+    /// Searches the chat of recent VODs in the directory, including from channels that are offline
+    DirectoryVideos {
+        /// Only include VODs in these languages, such as "en"
+        #[clap(short, long)]
+        language: Vec<String>,
+
+        /// Order of the VODs, either TIME or VIEWS
+        #[clap(long, default_value = "TIME")]
+        sort: crate::twitch::VideoSort,
+
+        /// Include highlights as well as past broadcasts
+        #[clap(long, parse(from_flag))]
+        highlights: bool,
+
+        /// Maximum number of VODs to search
+        #[clap(long, default_value = "100")]
+        limit: usize,
+    },
 }
 
 #[derive(Subcommand)]
@@ -77,6 +188,18 @@ enum RecoverOpts {
 
         #[clap(short, long)]
         username: String,
+
+        /// Also try start times up to this many seconds before and after the given one
+        #[clap(short, long, default_value = "0")]
+        window: u32,
+
+        /// Maximum number of requests to have in flight at once
+        #[clap(short, long, default_value = "16")]
+        concurrency: usize,
+
+        /// Directory to save a playable playlist and metadata of the recovered VOD into
+        #[clap(short, long)]
+        output: Option<std::path::PathBuf>,
     },
 }
 
@@ -85,6 +208,28 @@ enum Twitch {
     Vod {
         id: u64,
 
+        /// List the ranges of the VOD that have been muted, and whether the original segments
+        /// are still available
+        #[clap(long, parse(from_flag))]
+        segments: bool,
+
+        /// Only search chat sent during chapters (games or categories) matching this pattern
+        #[clap(long, value_parser)]
+        chapter: Option<regex::Regex>,
+
+        /// Only show messages of these kinds: an event such as sub, giftsub, raid or deleted, a
+        /// reply or a cheer
+        #[clap(long)]
+        event: Vec<String>,
+
+        /// List the chapters of the VOD, with how many messages were sent (and matched) in each
+        #[clap(long, parse(from_flag))]
+        chapters: bool,
+
+        /// Write a playlist that uses the original segments in place of muted ones where possible
+        #[clap(long)]
+        unmuted_playlist: Option<std::path::PathBuf>,
+
         #[clap(subcommand)]
         recover: Option<RecoverOpts>,
     },
@@ -95,12 +240,17 @@ enum Twitch {
         opts: TwitchChannelOpts,
     },
     Directory {
+        /// Name, slug or ID of the category
         name: String,
 
-        /// Retrieves the clips associated with directory, which can be from channels that are no
-        /// longer live
+        /// Retrieves the clips or VODs associated with directory, which can be from channels that
+        /// are no longer live
         #[clap(subcommand)]
-        directory_clips: Option<DirectoryClips>,
+        listing: Option<DirectoryListing>,
+
+        /// Live stream options
+        #[clap(flatten)]
+        streams: StreamOpts,
 
         /// Channel Options
         #[clap(flatten)]
@@ -109,10 +259,73 @@ enum Twitch {
     Tags {
         tags: Vec<String>,
 
+        /// Live stream options
+        #[clap(flatten)]
+        streams: StreamOpts,
+
         /// Channel Options
         #[clap(flatten)]
         opts: TwitchChannelOpts,
     },
+    /// Scan every member of a Twitch team
+    Team {
+        name: String,
+
+        /// Channel Options
+        #[clap(flatten)]
+        opts: TwitchChannelOpts,
+    },
+    /// Scan every channel in a file of usernames, one per line
+    Channels {
+        file: std::path::PathBuf,
+
+        /// Channel Options
+        #[clap(flatten)]
+        opts: TwitchChannelOpts,
+    },
+    /// Show the details of a channel
+    Info { channel: crate::twitch::Channel },
+    /// List the BTTV, FFZ and 7TV emotes that can be used in a channel's chat
+    Emotes {
+        channel: crate::twitch::Channel,
+
+        /// Instead count how often each emote, including Twitch emotes, was used in this VOD
+        #[clap(long)]
+        vod: Option<u32>,
+    },
+    /// Check which of the persisted query hashes used for Twitch's API are out of date
+    Doctor {
+        /// Channel to use when an operation needs one
+        #[clap(long, default_value = "twitch")]
+        channel: String,
+    },
+    /// Recover VODs from a CSV of username,stream_id,start_time rows
+    RecoverBatch {
+        /// File to read the rows from, or stdin when not given
+        file: Option<std::path::PathBuf>,
+
+        /// Also try start times up to this many seconds before and after the given ones
+        #[clap(short, long, default_value = "0")]
+        window: u32,
+
+        /// Maximum number of requests to have in flight at once, shared between all rows
+        #[clap(short, long, default_value = "32")]
+        concurrency: usize,
+    },
+    /// Capture live chat from one or more channels as it happens
+    Live {
+        #[clap(required = true)]
+        channels: Vec<String>,
+
+        /// IRC server to connect to
+        #[clap(long, default_value = crate::twitchlive::IRC_SERVER)]
+        server: String,
+
+        /// Only show messages of these kinds: an event such as sub, giftsub, raid or deleted, a
+        /// reply or a cheer
+        #[clap(long)]
+        event: Vec<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -153,12 +366,98 @@ enum Mode {
         #[clap(subcommand)]
         tiktok: TikTok,
     },
+
+    /// List the variants of an HLS playlist, or download it into a single .ts file
+    Hls {
+        url: String,
+
+        /// Quality to use, either a Twitch quality directory such as 720p60, or a variant name or
+        /// resolution from a master playlist
+        #[clap(short, long)]
+        quality: Option<String>,
+
+        /// File to download the stream into
+        #[clap(short, long)]
+        output: Option<std::path::PathBuf>,
+
+        /// Number of segments to download at once
+        #[clap(short = 'j', long, default_value = "8")]
+        concurrency: usize,
+    },
+
+    /// Browse and search the chat of a channel's VODs in a terminal UI
+    Browse {
+        /// Either twitch or afreecatv
+        platform: crate::common::Platform,
+
+        channel: String,
+    },
+
+    /// Print a VOD's chat in time with playback, following mpv or a simulated clock
+    Replay {
+        /// Either twitch or afreecatv
+        platform: crate::common::Platform,
+
+        id: u32,
+
+        /// mpv's JSON IPC socket, as opened with `mpv --input-ipc-server=<socket>`. Without it,
+        /// chat is replayed on a simulated clock
+        #[cfg(unix)]
+        #[clap(long)]
+        mpv: Option<std::path::PathBuf>,
+
+        /// Seconds into the VOD to start the simulated clock at
+        #[clap(long, default_value = "0")]
+        start: f64,
+
+        /// Speed of the simulated clock
+        #[clap(long, default_value = "1")]
+        speed: f64,
+    },
+
+    /// Poll channels for new VODs, archiving their chat and reporting matches from saved queries
+    Watch {
+        /// Twitch channels to watch
+        #[clap(long)]
+        twitch: Vec<String>,
+
+        /// AfreecaTV BJs to watch
+        #[clap(long)]
+        afreecatv: Vec<String>,
+
+        /// Seconds to wait between polls
+        #[clap(short, long, default_value = "600")]
+        interval: u64,
+
+        /// File that records which VODs have already been indexed
+        #[clap(short, long, default_value = "watch_state.json")]
+        state: std::path::PathBuf,
+
+        /// Index the VODs that already exist when a channel is first watched
+        #[clap(short, long, parse(from_flag))]
+        backfill: bool,
+    },
+}
+
+fn write_clip(task: &oqueue::Task, clip: &crate::twitch::clips::Clip, context: Option<f64>) {
+    writeln!(task, "{}", clip);
+    if let Some(before) = context {
+        match clip.context(before) {
+            Ok(messages) => messages
+                .iter()
+                .for_each(|message| writeln!(task, "    {}", message)),
+            Err(e) => eprintln!("{}", e),
+        }
+        writeln!(task);
+    }
 }
 
 fn handle_twitch_channel(
     channel: crate::twitch::Channel,
     opts: &TwitchChannelOpts,
     filter: &regex::Regex,
+    alerts: Option<&crate::rules::Engine>,
+    archive: Option<&crate::archive::Archive>,
     sequence: &oqueue::Sequencer,
 ) {
     rayon::scope(|t| {
@@ -182,7 +481,7 @@ fn handle_twitch_channel(
                 } else {
                     clips
                         .filter(|c| filter.is_match(&c.username) || filter.is_match(&c.title))
-                        .for_each(|c| writeln!(&task, "{}", c))
+                        .for_each(|c| write_clip(&task, &c, opts.clip_context))
                 }
             });
         }
@@ -190,13 +489,50 @@ fn handle_twitch_channel(
         if opts.vods {
             t.spawn(|_| {
                 let videos = channel.videos().unwrap();
-                crate::common::print_iter(&videos, filter, opts.showall, sequence);
+                crate::common::print_iter(
+                    &videos,
+                    filter,
+                    opts.chapter.as_ref(),
+                    &opts.event,
+                    opts.showall,
+                    alerts,
+                    archive,
+                    sequence,
+                );
             });
         }
 
         if opts.recover {
-            let channel = crate::twitchrecover::Channel::new(&channel.username).unwrap();
-            channel.videos().unwrap();
+            let task = sequence.begin();
+            let recover_channel = if opts.history.is_empty() {
+                crate::twitchrecover::Channel::new(&channel.username)
+            } else {
+                crate::twitchrecover::Channel::with_sources(
+                    &channel.username,
+                    opts.history
+                        .iter()
+                        .map(|spec| crate::twitchrecover::history::from_spec(spec))
+                        .collect(),
+                )
+            };
+            match recover_channel.videos() {
+                Ok(videos) => {
+                    if let Some(archive) = archive {
+                        let source = format!("twitchrecover/{}", channel.username);
+                        if let Err(e) = archive.append_records(
+                            &source,
+                            videos.iter().map(crate::twitchrecover::Video::to_json),
+                        ) {
+                            eprintln!("{}", e);
+                        }
+                    }
+                    videos
+                        .iter()
+                        .filter(|video| video.is_match(filter))
+                        .for_each(|video| writeln!(task, "{}\n", video));
+                }
+                Err(e) => eprintln!("{}", e),
+            }
         }
     });
 }
@@ -204,69 +540,443 @@ fn handle_twitch_channel(
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
     let filter = args.filter;
+    let images = args.images;
+    if images
+        && !matches!(
+            &args.mode,
+            Mode::Twitch {
+                twitch: Twitch::Vod { .. } | Twitch::Live { .. }
+            } | Mode::Replay {
+                platform: crate::common::Platform::Twitch,
+                ..
+            }
+        )
+    {
+        return Err(
+            "--images is only supported by twitch vod, twitch live and replay twitch".into(),
+        );
+    }
+    if args.archive.is_some()
+        && !matches!(
+            &args.mode,
+            Mode::Twitch {
+                twitch: Twitch::Channel { .. }
+                    | Twitch::Directory { .. }
+                    | Twitch::Tags { .. }
+                    | Twitch::Team { .. }
+                    | Twitch::Channels { .. }
+                    | Twitch::Live { .. }
+            } | Mode::Afreecatv {
+                afreecatv: Afreecatv::Blog { .. }
+            } | Mode::Watch { .. }
+        )
+    {
+        return Err("--archive is only supported by twitch live, watch, afreecatv blog and the twitch channel, directory, tags, team and channels modes".into());
+    }
+    let archive = args.archive.map(crate::archive::Archive::new).transpose()?;
+    let alerts = if args.alerts {
+        Some(crate::rules::Engine::from_config(&crate::config::CONFIG)?)
+    } else {
+        None
+    };
+    let alerts = alerts.as_ref();
 
     rayon::ThreadPoolBuilder::build_global(rayon::ThreadPoolBuilder::new().num_threads(32))?;
     let sequence = oqueue::Sequencer::stdout();
 
     match args.mode {
         Mode::Twitch { twitch } => match twitch {
-            Twitch::Vod { id, recover } => {
-                if let Some(rec) = recover {
+            Twitch::Vod {
+                id,
+                segments,
+                chapter,
+                event,
+                chapters,
+                unmuted_playlist,
+                recover,
+            } => {
+                if segments || unmuted_playlist.is_some() {
+                    let vod = crate::twitch::Vod::fetch(id as u32)?;
+                    let url = vod
+                        .playlist()
+                        .ok_or("Unable to find the playlist for this VOD")?;
+                    let (url, mut media) = crate::hls::fetch_media(url, None)?;
+                    let muted = media.muted_segments(&url, true);
+
+                    println!("{}", vod);
+                    for range in crate::hls::muted_ranges(&muted) {
+                        let first = &range[0];
+                        let last = &range[range.len() - 1];
+                        println!(
+                            "Muted {} - {} ({} segments, {} unmuted available)",
+                            std::time::Duration::from_secs(first.offset as u64).hhmmss(),
+                            std::time::Duration::from_secs((last.offset + last.duration) as u64)
+                                .hhmmss(),
+                            range.len(),
+                            range.iter().filter(|s| s.unmuted.is_some()).count()
+                        );
+                    }
+                    if muted.is_empty() {
+                        println!("No muted segments");
+                    }
+
+                    if let Some(path) = unmuted_playlist {
+                        media.unmute(&muted);
+                        std::fs::write(&path, media.to_m3u8(&url))?;
+                        println!("Wrote {}", path.display());
+                    }
+                } else if let Some(rec) = recover {
                     match rec {
                         RecoverOpts::Recover {
                             start_time,
                             username,
+                            window,
+                            concurrency,
+                            output,
                         } => {
                             let timestamp = crate::twitchrecover::Channel::unix_time(&start_time)?;
-                            if let Some(vod) =
-                                crate::twitchrecover::Video::new(id, timestamp, &username, None)
-                            {
+                            if let Some((vod, offset)) = crate::twitchrecover::Video::search(
+                                id,
+                                timestamp,
+                                &username,
+                                None,
+                                window,
+                                concurrency,
+                            )? {
                                 println!("{}", vod);
+                                if offset != 0 {
+                                    println!("Found with a start time offset of {:+}s", offset);
+                                }
+                                if let Some(dir) = output {
+                                    println!("Saved {}", vod.save(&dir)?.display());
+                                }
                             } else {
                                 eprintln!("Unable to recover vod");
                             }
                         }
                     }
+                } else if chapters {
+                    let list = crate::twitch::Chapter::fetch(id as u32)?;
+                    let mut counts = vec![(0, 0); list.len()];
+                    let comments =
+                        crate::twitch::Vod::new(id as u32).comments_with_chapters(list.clone());
+                    for message in comments.flatten() {
+                        let timestamp = message.timestamp.unwrap_or_default();
+                        if let Some(i) = list.iter().rposition(|c| c.start <= timestamp) {
+                            counts[i].0 += 1;
+                            if message.is_match(&filter) {
+                                counts[i].1 += 1;
+                            }
+                        }
+                    }
+                    for (chapter, (total, matched)) in list.iter().zip(counts) {
+                        println!(
+                            "[{} - {}] {} ({} messages, {} matched)",
+                            std::time::Duration::from_secs(chapter.start as u64).hhmmss(),
+                            std::time::Duration::from_secs(
+                                (chapter.start + chapter.duration) as u64
+                            )
+                            .hhmmss(),
+                            chapter.description,
+                            total,
+                            matched
+                        );
+                    }
                 } else {
                     let vod = crate::twitch::Vod::new(id as u32);
+                    let owner = if images || alerts.is_some() {
+                        Some(vod.owner()?)
+                    } else {
+                        None
+                    };
+                    let channel = match &owner {
+                        Some(owner) => owner.username.to_owned(),
+                        None => vod.channel(),
+                    };
+                    let mut renderer = match &owner {
+                        Some(owner) if images => Some(crate::render::Renderer::new(owner)?),
+                        _ => None,
+                    };
+                    let vod_id = id.to_string();
                     vod.comments()
                         .flatten()
+                        .inspect(|m| {
+                            alerts
+                                .iter()
+                                .for_each(|a| a.process(&channel, Some(&vod_id), m))
+                        })
                         .filter(|m| {
-                            filter.is_match(&m.body) || filter.is_match(m.user.as_ref().unwrap())
+                            m.is_match(&filter)
+                                && m.in_chapter(chapter.as_ref())
+                                && m.is_kind(&event)
                         })
-                        .for_each(|comment| println!("{}", comment));
+                        .for_each(|comment| match renderer.as_mut() {
+                            Some(renderer) => println!("{}", renderer.render(&comment)),
+                            None => println!("{}", comment),
+                        });
                 }
             }
 
             Twitch::Channel { channel, opts } => {
-                handle_twitch_channel(channel, &opts, &filter, &oqueue::Sequencer::stdout());
+                channel.validate()?;
+                handle_twitch_channel(
+                    channel,
+                    &opts,
+                    &filter,
+                    alerts,
+                    archive.as_ref(),
+                    &oqueue::Sequencer::stdout(),
+                );
             }
 
             Twitch::Directory {
                 name,
-                directory_clips,
+                listing,
+                streams,
                 opts,
             } => {
-                let directory = crate::twitch::Directory::new(&name);
+                let directory = crate::twitch::Directory::resolve(&name)?;
+                println!("Directory: {}", directory.to_string().bold());
 
-                if let Some(DirectoryClips::DirectoryClips { recency }) = directory_clips {
-                    directory
-                        .clips(recency)
-                        .flatten()
-                        .filter(|c| filter.is_match(&c.username) || filter.is_match(&c.title))
-                        .for_each(|c| println!("{}", c));
+                match listing {
+                    Some(DirectoryListing::DirectoryClips { recency }) => {
+                        let task = sequence.begin();
+                        directory
+                            .clips(recency)
+                            .flatten()
+                            .filter(|c| filter.is_match(&c.username) || filter.is_match(&c.title))
+                            .for_each(|c| write_clip(&task, &c, opts.clip_context));
+                    }
+                    Some(DirectoryListing::DirectoryVideos {
+                        language,
+                        sort,
+                        highlights,
+                        limit,
+                    }) => {
+                        let videos: Vec<crate::twitch::Vod> = directory
+                            .videos(&language, sort, highlights)
+                            .flatten()
+                            .take(limit)
+                            .collect();
+                        crate::common::print_iter(
+                            &videos,
+                            &filter,
+                            opts.chapter.as_ref(),
+                            &opts.event,
+                            opts.showall,
+                            alerts,
+                            archive.as_ref(),
+                            &sequence,
+                        );
+                    }
+                    None => {}
                 }
 
-                for channel in directory.channels().flatten() {
+                for channel in directory.channels(streams.filter()).flatten() {
                     println!("Working on {}", channel.username.bold());
-                    handle_twitch_channel(channel, &opts, &filter, &sequence);
+                    handle_twitch_channel(
+                        channel,
+                        &opts,
+                        &filter,
+                        alerts,
+                        archive.as_ref(),
+                        &sequence,
+                    );
                 }
             }
 
-            Twitch::Tags { tags, opts } => {
-                for channel in crate::twitch::Tag::channels(&tags).flatten() {
+            Twitch::Tags {
+                tags,
+                streams,
+                opts,
+            } => {
+                for channel in crate::twitch::Tag::channels(&tags, streams.filter()).flatten() {
                     println!("Working on {}", channel.username.bold());
-                    handle_twitch_channel(channel, &opts, &filter, &sequence);
+                    handle_twitch_channel(
+                        channel,
+                        &opts,
+                        &filter,
+                        alerts,
+                        archive.as_ref(),
+                        &sequence,
+                    );
+                }
+            }
+
+            Twitch::Team { name, opts } => {
+                for channel in crate::twitch::Team::new(name).members()? {
+                    println!("Working on {}", channel.username.bold());
+                    handle_twitch_channel(
+                        channel,
+                        &opts,
+                        &filter,
+                        alerts,
+                        archive.as_ref(),
+                        &sequence,
+                    );
+                }
+            }
+
+            Twitch::Channels { file, opts } => {
+                for channel in crate::twitch::Channel::from_file(&file)? {
+                    if let Err(e) = channel.validate() {
+                        eprintln!("{}", e);
+                        continue;
+                    }
+                    println!("Working on {}", channel.username.bold());
+                    handle_twitch_channel(
+                        channel,
+                        &opts,
+                        &filter,
+                        alerts,
+                        archive.as_ref(),
+                        &sequence,
+                    );
+                }
+            }
+
+            Twitch::Info { channel } => {
+                println!("{}", channel.info()?);
+            }
+
+            Twitch::Emotes { channel, vod } => {
+                let info = channel.info()?;
+                let catalog = crate::emotes::Catalog::load(
+                    &info.id,
+                    &crate::emotes::providers(),
+                    &crate::emotes::Cache::new(),
+                );
+
+                if let Some(id) = vod {
+                    let mut counts: std::collections::HashMap<(String, String), usize> =
+                        std::collections::HashMap::new();
+                    for message in crate::twitch::Vod::new(id).comments().flatten() {
+                        for token in catalog.tokens(&message) {
+                            if let crate::emotes::Token::Emote(emote) = token {
+                                *counts.entry((emote.provider, emote.name)).or_default() += 1;
+                            }
+                        }
+                    }
+                    let mut counts: Vec<((String, String), usize)> = counts
+                        .into_iter()
+                        .filter(|((_, name), _)| filter.is_match(name))
+                        .collect();
+                    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+                    for ((provider, name), count) in counts {
+                        println!("{:>8} [{}] {}", count, provider, name.bold());
+                    }
+                } else {
+                    let mut emotes: Vec<&crate::emotes::Emote> = catalog
+                        .emotes()
+                        .filter(|emote| filter.is_match(&emote.name))
+                        .collect();
+                    emotes.sort_by(|a, b| (&a.provider, &a.name).cmp(&(&b.provider, &b.name)));
+                    for emote in emotes {
+                        println!("[{}] {} {}", emote.provider, emote.name.bold(), emote.url);
+                    }
+                }
+            }
+
+            Twitch::Doctor { channel } => {
+                for (operation, hash, health) in crate::twitch::doctor(&channel) {
+                    match health {
+                        crate::twitch::QueryHealth::Ok => {
+                            println!("[{}] {} {}", "ok".green(), operation, hash)
+                        }
+                        crate::twitch::QueryHealth::Stale => {
+                            println!("[{}] {} {}", "stale".red(), operation, hash)
+                        }
+                        crate::twitch::QueryHealth::Error(e) => {
+                            println!("[{}] {} {}", "error".yellow(), operation, e)
+                        }
+                    }
+                }
+            }
+
+            Twitch::RecoverBatch {
+                file,
+                window,
+                concurrency,
+            } => {
+                let input = match file {
+                    Some(path) => std::fs::read_to_string(path)?,
+                    None => std::io::read_to_string(std::io::stdin())?,
+                };
+                let rows = crate::twitchrecover::recover_batch(&input, window, concurrency)?;
+
+                println!("row    username             stream         status     result");
+                for row in rows {
+                    let (status, result) = match row.result {
+                        Ok(Some((video, offset))) => (
+                            format!("{:<10}", "found").green(),
+                            if offset == 0 {
+                                video.link
+                            } else {
+                                format!("{} ({:+}s)", video.link, offset)
+                            },
+                        ),
+                        Ok(None) => (format!("{:<10}", "not found").yellow(), String::new()),
+                        Err(e) => (format!("{:<10}", "error").red(), e),
+                    };
+                    println!(
+                        "{:<6} {:<20} {:<14} {} {}",
+                        row.line, row.username, row.stream_id, status, result
+                    );
+                }
+            }
+
+            Twitch::Live {
+                channels,
+                server,
+                event,
+            } => {
+                let mut renderers = std::collections::HashMap::new();
+                if images {
+                    for channel in &channels {
+                        let renderer =
+                            crate::render::Renderer::new(&crate::twitch::Channel::new(channel))?;
+                        renderers.insert(channel.to_lowercase(), renderer);
+                    }
+                }
+                let source = |message: &crate::common::Message| -> String {
+                    message
+                        .channel
+                        .clone()
+                        .unwrap_or_else(|| String::from("live"))
+                };
+                for messages in crate::twitchlive::LiveChat::connect(&server, &channels)? {
+                    if let Some(archive) = &archive {
+                        let mut sources: Vec<String> = messages.iter().map(source).collect();
+                        sources.sort_unstable();
+                        sources.dedup();
+                        for name in sources {
+                            archive.append_records(
+                                &name,
+                                messages
+                                    .iter()
+                                    .filter(|message| source(message) == name)
+                                    .map(|message| message.to_json()),
+                            )?;
+                        }
+                    }
+                    for message in &messages {
+                        alerts
+                            .iter()
+                            .for_each(|a| a.process(&source(message), None, message));
+                    }
+                    messages
+                        .iter()
+                        .filter(|m| m.is_match(&filter) && m.is_kind(&event))
+                        .for_each(|message| {
+                            let renderer = message
+                                .channel
+                                .as_ref()
+                                .and_then(|channel| renderers.get_mut(channel));
+                            match renderer {
+                                Some(renderer) => println!("{}", renderer.render(message)),
+                                None => println!("{}", message),
+                            }
+                        });
                 }
             }
         },
@@ -274,18 +984,32 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Mode::Afreecatv { afreecatv } => match afreecatv {
             Afreecatv::Vod { id } => {
                 let vod = crate::afreecatv::Vod::new(id)?;
+                let channel = vod.channel();
+                let vod_id = vod.id();
                 vod.comments()
                     .flatten()
-                    .filter(|m| {
-                        filter.is_match(&m.body) || filter.is_match(m.user.as_ref().unwrap())
+                    .inspect(|m| {
+                        alerts
+                            .iter()
+                            .for_each(|a| a.process(&channel, Some(&vod_id), m))
                     })
+                    .filter(|m| m.is_match(&filter))
                     .for_each(|comment| println!("{}", comment));
             }
 
             Afreecatv::Blog { username, showall } => {
                 let channel = crate::afreecatv::Channel::new(username);
                 let videos = channel.videos()?;
-                crate::common::print_iter(&videos, &filter, showall, &oqueue::Sequencer::stdout());
+                crate::common::print_iter(
+                    &videos,
+                    &filter,
+                    None,
+                    &[],
+                    showall,
+                    alerts,
+                    archive.as_ref(),
+                    &oqueue::Sequencer::stdout(),
+                );
             }
         },
 
@@ -300,9 +1024,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     video
                         .comments()
                         .flatten()
-                        .filter(|m| {
-                            filter.is_match(&m.body) || filter.is_match(m.user.as_ref().unwrap())
-                        })
+                        .filter(|m| m.is_match(&filter))
                         .for_each(|comment| println!("{}", comment));
                 }
                 if transcript {
@@ -314,6 +1036,147 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         },
+
+        Mode::Hls {
+            url,
+            quality,
+            output,
+            concurrency,
+        } => {
+            let twitch_url = url.contains("/chunked/");
+            let url = match (&quality, twitch_url) {
+                (Some(quality), true) => {
+                    crate::hls::with_quality(&url, quality).ok_or("Invalid playlist URL")?
+                }
+                _ => url,
+            };
+            let master_quality = quality.as_deref().filter(|_| !twitch_url);
+
+            if let Some(output) = output {
+                let (media_url, media) = crate::hls::fetch_media(&url, master_quality)?;
+                println!(
+                    "Downloading {} segments ({}) into {}",
+                    media.segments.len(),
+                    std::time::Duration::from_secs(media.duration() as u64).hhmmss(),
+                    output.display()
+                );
+                crate::hls::download(&media_url, &media, &output, concurrency)?;
+            } else if twitch_url {
+                for quality in crate::hls::probe_variants(&url) {
+                    println!(
+                        "[{}] {}",
+                        quality,
+                        crate::hls::with_quality(&url, &quality).unwrap_or_default()
+                    );
+                }
+            } else {
+                match crate::hls::fetch(&url)? {
+                    crate::hls::Playlist::Master(variants) => {
+                        for mut variant in variants {
+                            variant.uri = crate::hls::resolve(&url, &variant.uri);
+                            println!("{}", variant);
+                        }
+                    }
+                    crate::hls::Playlist::Media(media) => {
+                        println!(
+                            "{} segments, {} ({})",
+                            media.segments.len(),
+                            std::time::Duration::from_secs(media.duration() as u64).hhmmss(),
+                            if media.ended { "complete" } else { "live" }
+                        );
+                    }
+                }
+            }
+        }
+
+        Mode::Browse { platform, channel } => match platform {
+            crate::common::Platform::Twitch => {
+                let channel = crate::twitch::Channel::new(channel);
+                channel.validate()?;
+                crate::tui::run(&channel.videos()?, &filter)?;
+            }
+            crate::common::Platform::Afreecatv => {
+                let videos = crate::afreecatv::Channel::new(channel).videos()?;
+                crate::tui::run(&videos, &filter)?;
+            }
+        },
+
+        Mode::Replay {
+            platform,
+            id,
+            #[cfg(unix)]
+            mpv,
+            start,
+            speed,
+        } => {
+            #[cfg(unix)]
+            let mut clock: Box<dyn crate::replay::Clock> = match mpv {
+                Some(socket) => Box::new(crate::replay::MpvClock::connect(&socket)?),
+                None => Box::new(crate::replay::SimulatedClock::new(start, speed)),
+            };
+            #[cfg(not(unix))]
+            let mut clock: Box<dyn crate::replay::Clock> =
+                Box::new(crate::replay::SimulatedClock::new(start, speed));
+            let (comments, channel, mut renderer) = match platform {
+                crate::common::Platform::Twitch => {
+                    let vod = crate::twitch::Vod::new(id);
+                    let owner = if images || alerts.is_some() {
+                        Some(vod.owner()?)
+                    } else {
+                        None
+                    };
+                    let channel = match &owner {
+                        Some(owner) => owner.username.to_owned(),
+                        None => vod.channel(),
+                    };
+                    let renderer = match &owner {
+                        Some(owner) if images => Some(crate::render::Renderer::new(owner)?),
+                        _ => None,
+                    };
+                    (vod.comments(), channel, renderer)
+                }
+                crate::common::Platform::Afreecatv => {
+                    let vod = crate::afreecatv::Vod::new(id)?;
+                    (vod.comments(), vod.channel(), None)
+                }
+            };
+            let vod_id = id.to_string();
+            crate::replay::replay(comments, clock.as_mut(), |replayed| match replayed {
+                crate::replay::Replayed::Message(message) => {
+                    alerts
+                        .iter()
+                        .for_each(|a| a.process(&channel, Some(&vod_id), message));
+                    if message.is_match(&filter) {
+                        match renderer.as_mut() {
+                            Some(renderer) => println!("{}", renderer.render(message)),
+                            None => println!("{}", message),
+                        }
+                    }
+                }
+                crate::replay::Replayed::Seek(position) => {
+                    let marker = format!(
+                        "-- {} --",
+                        std::time::Duration::from_secs(position as u64).hhmmss()
+                    );
+                    println!("{}", marker.dimmed());
+                }
+            })?;
+        }
+
+        Mode::Watch {
+            twitch,
+            afreecatv,
+            interval,
+            state,
+            backfill,
+        } => {
+            let mut watcher =
+                crate::watch::Watcher::new(&state, &filter, archive.as_ref(), alerts, backfill)?;
+            loop {
+                watcher.poll(&twitch, &afreecatv);
+                std::thread::sleep(std::time::Duration::from_secs(interval));
+            }
+        }
     }
     Ok(())
 }
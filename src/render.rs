@@ -0,0 +1,388 @@
+use colored::Colorize;
+use sha1::Digest;
+
+/// Height in pixels that images are scaled to, a multiple of six so that sixel bands line up
+const IMAGE_HEIGHT: u32 = 18;
+
+/// How images are drawn in the terminal
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    Text,
+}
+
+impl Protocol {
+    /// Read from `render.protocol` in the config ("kitty", "sixel" or "text"), otherwise guessed
+    /// from the terminal
+    pub fn detect() -> Self {
+        if let Some(protocol) = crate::config::CONFIG
+            .get("render")
+            .and_then(|r| r.get("protocol"))
+            .and_then(|p| p.as_str())
+        {
+            match protocol {
+                "kitty" => return Protocol::Kitty,
+                "sixel" => return Protocol::Sixel,
+                "text" => return Protocol::Text,
+                _ => eprintln!("Unknown render protocol {}", protocol),
+            }
+        }
+
+        let term = std::env::var("TERM").unwrap_or_default();
+        let program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+        if std::env::var("KITTY_WINDOW_ID").is_ok()
+            || term.contains("kitty")
+            || program == "WezTerm"
+            || program == "ghostty"
+        {
+            Protocol::Kitty
+        } else if term.contains("sixel")
+            || term.starts_with("foot")
+            || term.starts_with("mlterm")
+            || term.starts_with("contour")
+        {
+            Protocol::Sixel
+        } else {
+            Protocol::Text
+        }
+    }
+}
+
+/// An emote or badge, decoded and scaled to the height of a line
+struct Image {
+    id: u32,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl Image {
+    /// Number of terminal cells the image covers, assuming cells are twice as tall as they are wide
+    fn columns(&self) -> u32 {
+        (self.width * 2).div_ceil(self.height).max(1)
+    }
+}
+
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Draws an image with the kitty graphics protocol, sending its pixels only the first time
+fn kitty(image: &Image, transmitted: &mut std::collections::HashSet<u32>) -> String {
+    let mut out = String::new();
+    if transmitted.insert(image.id) {
+        let data = base64(&image.rgba);
+        let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let more = u8::from(i + 1 < chunks.len());
+            if i == 0 {
+                out.push_str(&format!(
+                    "\x1b_Ga=t,f=32,s={},v={},i={},q=2,m={};",
+                    image.width, image.height, image.id, more
+                ));
+            } else {
+                out.push_str(&format!("\x1b_Gm={};", more));
+            }
+            out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+            out.push_str("\x1b\\");
+        }
+    }
+    out.push_str(&format!(
+        "\x1b_Ga=p,i={},r=1,c={},q=2\x1b\\",
+        image.id,
+        image.columns()
+    ));
+    out
+}
+
+/// Collapses runs of the same sixel into a repeat introducer
+fn sixel_rle(row: &[u8]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < row.len() {
+        let run = row[i..].iter().take_while(|&&c| c == row[i]).count();
+        if run > 3 {
+            out.push_str(&format!("!{}{}", run, row[i] as char));
+        } else {
+            (0..run).for_each(|_| out.push(row[i] as char));
+        }
+        i += run;
+    }
+    out
+}
+
+/// Draws an image as sixels, quantized to a 6x6x6 color cube, leaving the cursor after it on the
+/// same line
+fn sixel(image: &Image) -> String {
+    let level = |c: u8| (c as u32 * 5 + 127) / 255;
+    let colors: Vec<Option<u32>> = image
+        .rgba
+        .chunks(4)
+        .map(|p| {
+            if p[3] < 128 {
+                None
+            } else {
+                Some(level(p[0]) * 36 + level(p[1]) * 6 + level(p[2]))
+            }
+        })
+        .collect();
+    let mut used: Vec<u32> = colors.iter().flatten().copied().collect();
+    used.sort_unstable();
+    used.dedup();
+
+    let mut out = format!("\x1b7\x1bP0;1;0q\"1;1;{};{}", image.width, image.height);
+    for color in &used {
+        out.push_str(&format!(
+            "#{};2;{};{};{}",
+            color,
+            color / 36 * 20,
+            color / 6 % 6 * 20,
+            color % 6 * 20
+        ));
+    }
+    for band in (0..image.height).step_by(6) {
+        for color in &used {
+            let row: Vec<u8> = (0..image.width)
+                .map(|x| {
+                    let bits = (0..6)
+                        .filter(|dy| band + dy < image.height)
+                        .filter(|dy| {
+                            colors[((band + dy) * image.width + x) as usize] == Some(*color)
+                        })
+                        .fold(0u8, |bits, dy| bits | 1 << dy);
+                    63 + bits
+                })
+                .collect();
+            if row.iter().any(|&c| c != 63) {
+                out.push_str(&format!("#{}{}$", color, sixel_rle(&row)));
+            }
+        }
+        out.push('-');
+    }
+    out.push_str(&format!("\x1b\\\x1b8\x1b[{}C", image.columns()));
+    out
+}
+
+/// Renders messages with their emotes and badges drawn inline, or colored when the terminal
+/// can't show images
+pub struct Renderer {
+    protocol: Protocol,
+    catalog: crate::emotes::Catalog,
+    badges: std::collections::HashMap<(String, String), String>,
+    dir: Option<std::path::PathBuf>,
+    images: std::collections::HashMap<String, Option<Image>>,
+    transmitted: std::collections::HashSet<u32>,
+}
+
+impl Renderer {
+    pub fn new(channel: &crate::twitch::Channel) -> Result<Self, Box<dyn std::error::Error>> {
+        let protocol = Protocol::detect();
+        let info = channel.info()?;
+        let catalog = crate::emotes::Catalog::load(
+            &info.id,
+            &crate::emotes::providers(),
+            &crate::emotes::Cache::new(),
+        );
+        let badges = if protocol == Protocol::Text {
+            std::collections::HashMap::new()
+        } else {
+            channel.badge_images().unwrap_or_else(|e| {
+                eprintln!("Unable to load badges: {}", e);
+                std::collections::HashMap::new()
+            })
+        };
+        Ok(Self {
+            protocol,
+            catalog,
+            badges,
+            dir: crate::config::data_dir().map(|dir| dir.join("images")),
+            images: std::collections::HashMap::new(),
+            transmitted: std::collections::HashSet::new(),
+        })
+    }
+
+    /// The image behind `url`, downloaded once and kept under the data directory
+    fn load(&self, url: &str, id: u32) -> Result<Image, Box<dyn std::error::Error>> {
+        let path = self
+            .dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{:x}", sha1::Sha1::digest(url.as_bytes()))));
+        let bytes = match path.as_ref().filter(|path| path.exists()) {
+            Some(path) => std::fs::read(path)?,
+            None => {
+                let bytes = crate::common::CLIENT
+                    .get(url)
+                    .header("User-Agent", crate::common::USER_AGENT)
+                    .send()?
+                    .error_for_status()?
+                    .bytes()?
+                    .to_vec();
+                if let Some(path) = &path {
+                    if let Some(dir) = path.parent() {
+                        std::fs::create_dir_all(dir)?;
+                    }
+                    std::fs::write(path, &bytes)?;
+                }
+                bytes
+            }
+        };
+
+        let image = image::load_from_memory(&bytes)?
+            .resize(
+                IMAGE_HEIGHT * 4,
+                IMAGE_HEIGHT,
+                image::imageops::FilterType::Triangle,
+            )
+            .to_rgba8();
+        Ok(Image {
+            id,
+            width: image.width(),
+            height: image.height(),
+            rgba: image.into_raw(),
+        })
+    }
+
+    /// Draws the image behind `url`, or returns `fallback` if it can't be shown
+    fn draw(&mut self, url: &str, fallback: String) -> String {
+        if self.protocol == Protocol::Text {
+            return fallback;
+        }
+        if !self.images.contains_key(url) {
+            let id = self.images.len() as u32 + 1;
+            let image = match self.load(url, id) {
+                Ok(image) => Some(image),
+                Err(e) => {
+                    eprintln!("Unable to load {}: {}", url, e);
+                    None
+                }
+            };
+            self.images.insert(url.to_owned(), image);
+        }
+        match (self.images.get(url), self.protocol) {
+            (Some(Some(image)), Protocol::Kitty) => kitty(image, &mut self.transmitted),
+            (Some(Some(image)), Protocol::Sixel) => sixel(image),
+            _ => fallback,
+        }
+    }
+
+    fn badge(&mut self, badge: &crate::common::Badge) -> String {
+        let fallback = match badge.name.as_str() {
+            "broadcaster" => Some("[B]".red()),
+            "moderator" => Some("[M]".green()),
+            "vip" => Some("[V]".magenta()),
+            "subscriber" | "founder" => Some("[S]".purple()),
+            _ => None,
+        }
+        .map(|badge| badge.to_string())
+        .unwrap_or_default();
+        match self
+            .badges
+            .get(&(badge.name.to_owned(), badge.version.to_owned()))
+            .cloned()
+        {
+            Some(url) => self.draw(&url, fallback),
+            None => fallback,
+        }
+    }
+
+    fn emote(&mut self, emote: &crate::emotes::Emote) -> String {
+        let fallback = match emote.provider.as_str() {
+            "twitch" => emote.name.purple(),
+            "bttv" => emote.name.red(),
+            "ffz" => emote.name.green(),
+            _ => emote.name.cyan(),
+        }
+        .to_string();
+        self.draw(&emote.url, fallback)
+    }
+
+    pub fn render(&mut self, message: &crate::common::Message) -> String {
+        let badges: String = message.badges.iter().map(|b| self.badge(b)).collect();
+        let body: String = self
+            .catalog
+            .tokens(message)
+            .iter()
+            .map(|token| match token {
+                crate::emotes::Token::Text(text) => text.to_owned(),
+                crate::emotes::Token::Emote(emote) => self.emote(emote),
+            })
+            .collect();
+
+        let mut out = String::new();
+        if message.write_with(&mut out, &badges, &body).is_err() {
+            return message.to_string();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xfb, 0xff, 0xbf]), "+/+/");
+    }
+
+    #[test]
+    fn compresses_sixel_runs() {
+        assert_eq!(sixel_rle(b""), "");
+        assert_eq!(sixel_rle(b"???"), "???");
+        assert_eq!(sixel_rle(b"????"), "!4?");
+        assert_eq!(sixel_rle(b"@@@@@AA~"), "!5@AA~");
+    }
+
+    #[test]
+    fn draws_sixels() {
+        // Red across the top, transparent then blue along the bottom
+        let image = Image {
+            id: 1,
+            width: 2,
+            height: 2,
+            rgba: vec![
+                255, 0, 0, 255, 255, 0, 0, 255, //
+                0, 0, 0, 0, 0, 0, 255, 255,
+            ],
+        };
+        assert_eq!(
+            sixel(&image),
+            "\x1b7\x1bP0;1;0q\"1;1;2;2#5;2;0;0;100#180;2;100;0;0#5?A$#180@@$-\x1b\\\x1b8\x1b[2C"
+        );
+    }
+
+    #[test]
+    fn draws_sixels_in_bands() {
+        // Seven rows need a second band, and runs of a color are compressed
+        let image = Image {
+            id: 1,
+            width: 5,
+            height: 7,
+            rgba: [255, 255, 255, 255].repeat(35),
+        };
+        assert_eq!(
+            sixel(&image),
+            "\x1b7\x1bP0;1;0q\"1;1;5;7#215;2;100;100;100#215!5~$-#215!5@$-\x1b\\\x1b8\x1b[2C"
+        );
+    }
+}
@@ -0,0 +1,341 @@
+#[cfg(unix)]
+use std::io::{BufRead, Write};
+
+/// How often the playback position is checked
+const TICK: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// A jump forward of more than this many seconds between ticks is treated as a seek, skipping the
+/// chat in between rather than printing all of it at once
+const SEEK_THRESHOLD: f64 = 10.0;
+
+/// Where in the VOD playback is
+pub struct Playback {
+    pub position: f64,
+    pub paused: bool,
+}
+
+pub trait Clock {
+    /// The current playback, `None` while nothing is playing yet, or an error once the player has
+    /// gone away
+    fn playback(&mut self) -> Result<Option<Playback>, Box<dyn std::error::Error>>;
+
+    /// Whether to keep running after the last message, since playback may still seek back
+    fn waits_for_seeks(&self) -> bool {
+        true
+    }
+
+    /// Waits until playback should be checked again
+    fn wait(&mut self) {
+        std::thread::sleep(TICK);
+    }
+}
+
+/// A clock that starts at an offset and runs at a fixed speed, for replaying without a player
+pub struct SimulatedClock {
+    started: std::time::Instant,
+    offset: f64,
+    speed: f64,
+}
+
+impl SimulatedClock {
+    pub fn new(offset: f64, speed: f64) -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            offset,
+            speed,
+        }
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn playback(&mut self) -> Result<Option<Playback>, Box<dyn std::error::Error>> {
+        Ok(Some(Playback {
+            position: self.offset + self.started.elapsed().as_secs_f64() * self.speed,
+            paused: false,
+        }))
+    }
+
+    fn waits_for_seeks(&self) -> bool {
+        false
+    }
+}
+
+/// Follows an mpv player through its JSON IPC socket, as opened with `--input-ipc-server`
+#[cfg(unix)]
+pub struct MpvClock {
+    reader: std::io::BufReader<std::os::unix::net::UnixStream>,
+    writer: std::os::unix::net::UnixStream,
+    request_id: u64,
+}
+
+#[cfg(unix)]
+impl MpvClock {
+    pub fn connect(socket: &std::path::Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let stream = std::os::unix::net::UnixStream::connect(socket)?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+        Ok(Self {
+            reader: std::io::BufReader::new(stream.try_clone()?),
+            writer: stream,
+            request_id: 0,
+        })
+    }
+
+    /// Reads a property, which is `None` while it is unavailable, such as before a file is loaded
+    fn get_property(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<serde_json::Value>, Box<dyn std::error::Error>> {
+        self.request_id += 1;
+        let request = serde_json::json!({
+            "command": ["get_property", name],
+            "request_id": self.request_id
+        });
+        writeln!(self.writer, "{}", request)?;
+
+        // Events are sent on the same socket, so skip lines until the reply arrives
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err("mpv closed the connection".into());
+            }
+            let reply: serde_json::Value = serde_json::from_str(&line)?;
+            if reply.get("request_id").and_then(|r| r.as_u64()) != Some(self.request_id) {
+                continue;
+            }
+            return match reply.get("error").and_then(|e| e.as_str()) {
+                Some("success") => Ok(reply.get("data").cloned()),
+                Some("property unavailable") => Ok(None),
+                Some(error) => Err(format!("mpv: {}", error).into()),
+                None => Err("Missing error".into()),
+            };
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Clock for MpvClock {
+    fn playback(&mut self) -> Result<Option<Playback>, Box<dyn std::error::Error>> {
+        let position = match self.get_property("playback-time")? {
+            Some(position) => position
+                .as_f64()
+                .ok_or("Could not convert playback-time -> f64")?,
+            None => return Ok(None),
+        };
+        let paused = self
+            .get_property("pause")?
+            .and_then(|p| p.as_bool())
+            .unwrap_or(false);
+        Ok(Some(Playback { position, paused }))
+    }
+}
+
+/// What happens during a replay, in the order it should be shown
+pub enum Replayed<'a> {
+    /// A message that playback has reached
+    Message(&'a crate::common::Message),
+    /// Playback jumped to this many seconds into the VOD
+    Seek(f64),
+}
+
+/// Passes chat to `print` as playback reaches it. Chat is loaded in the background while the
+/// replay runs, and seeks in either direction continue from the new position.
+pub fn replay<F>(
+    comments: Box<dyn crate::common::ChatIterator>,
+    clock: &mut dyn Clock,
+    mut print: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnMut(Replayed),
+{
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for messages in comments {
+            if sender.send(messages).is_err() {
+                break;
+            }
+        }
+    });
+
+    let timestamp = |message: &crate::common::Message| message.timestamp.unwrap_or_default();
+    let mut messages: Vec<crate::common::Message> = Vec::new();
+    let mut loaded = false;
+    let mut next = 0;
+    let mut last: Option<f64> = None;
+    let mut skip_until = 0.0;
+    // Wait for the first page, so that playback has something to catch up with
+    match receiver.recv() {
+        Ok(batch) => messages.extend(batch),
+        Err(_) => loaded = true,
+    }
+    loop {
+        loop {
+            match receiver.try_recv() {
+                Ok(batch) => messages.extend(batch),
+                Err(std::sync::mpsc::TryRecvError::Empty) => break,
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    loaded = true;
+                    break;
+                }
+            }
+        }
+
+        let playback = match clock.playback() {
+            Ok(playback) => playback,
+            Err(e) => {
+                eprintln!("Stopped following playback: {}", e);
+                return Ok(());
+            }
+        };
+        if let Some(playback) = playback {
+            let position = playback.position;
+            let seeked = match last {
+                Some(last) => position < last - 1.0 || position > last + SEEK_THRESHOLD,
+                None => position > SEEK_THRESHOLD,
+            };
+            if seeked {
+                print(Replayed::Seek(position));
+                next = messages.partition_point(|m| timestamp(m) < position);
+                skip_until = position;
+            }
+            last = Some(position);
+
+            if !playback.paused {
+                while let Some(message) = messages.get(next) {
+                    if timestamp(message) > position {
+                        break;
+                    }
+                    if timestamp(message) >= skip_until {
+                        print(Replayed::Message(message));
+                    }
+                    next += 1;
+                }
+            }
+
+            if loaded && next >= messages.len() && !clock.waits_for_seeks() {
+                return Ok(());
+            }
+        }
+        clock.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock that plays back a script of positions, one per tick, and fails once it runs out
+    struct ScriptedClock {
+        steps: std::collections::VecDeque<(f64, bool)>,
+    }
+
+    impl ScriptedClock {
+        fn new(steps: &[(f64, bool)]) -> Self {
+            Self {
+                steps: steps.iter().copied().collect(),
+            }
+        }
+    }
+
+    impl Clock for ScriptedClock {
+        fn playback(&mut self) -> Result<Option<Playback>, Box<dyn std::error::Error>> {
+            let (position, paused) = *self.steps.front().ok_or("End of script")?;
+            Ok(Some(Playback { position, paused }))
+        }
+
+        fn wait(&mut self) {
+            self.steps.pop_front();
+        }
+    }
+
+    /// Chat with a message every second, from 1 to 30 seconds in
+    struct StubChat(Option<Vec<crate::common::Message>>);
+
+    impl StubChat {
+        fn new() -> Box<Self> {
+            let messages = (1..=30)
+                .map(|second| crate::common::Message {
+                    body: second.to_string(),
+                    timestamp: Some(second as f64),
+                    ..Default::default()
+                })
+                .collect();
+            Box::new(Self(Some(messages)))
+        }
+    }
+
+    impl crate::common::ChatIterator for StubChat {}
+    impl Iterator for StubChat {
+        type Item = Vec<crate::common::Message>;
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.take()
+        }
+    }
+
+    /// Replays the stub chat along `steps`, returning what was shown
+    fn run(steps: &[(f64, bool)]) -> Vec<String> {
+        let mut shown = Vec::new();
+        replay(
+            StubChat::new(),
+            &mut ScriptedClock::new(steps),
+            |replayed| {
+                shown.push(match replayed {
+                    Replayed::Message(message) => message.body.to_owned(),
+                    Replayed::Seek(position) => format!("seek {}", position),
+                })
+            },
+        )
+        .unwrap();
+        shown
+    }
+
+    #[test]
+    fn follows_playback() {
+        assert_eq!(
+            run(&[(0.0, false), (1.5, false), (3.0, false)]),
+            ["1", "2", "3"]
+        );
+    }
+
+    #[test]
+    fn holds_chat_while_paused() {
+        let shown = run(&[(1.0, false), (2.5, true), (2.5, true), (3.0, false)]);
+        assert_eq!(shown, ["1", "2", "3"]);
+
+        let shown = run(&[(1.0, false), (2.5, true)]);
+        assert_eq!(shown, ["1"]);
+    }
+
+    #[test]
+    fn seeks_back() {
+        let shown = run(&[(3.0, false), (1.5, false), (2.0, false)]);
+        assert_eq!(shown, ["1", "2", "3", "seek 1.5", "2"]);
+    }
+
+    #[test]
+    fn seeks_forward() {
+        let shown = run(&[(1.0, false), (20.0, false), (21.0, false)]);
+        assert_eq!(shown, ["1", "seek 20", "20", "21"]);
+
+        // Starting far into the VOD is a seek too, rather than showing everything before it
+        let shown = run(&[(25.0, false), (26.0, false)]);
+        assert_eq!(shown, ["seek 25", "25", "26"]);
+    }
+
+    #[test]
+    fn stops_with_a_simulated_clock() {
+        let mut shown = 0;
+        replay(
+            StubChat::new(),
+            &mut SimulatedClock::new(29.5, 1.0),
+            |replayed| {
+                if let Replayed::Message(_) = replayed {
+                    shown += 1;
+                }
+            },
+        )
+        .unwrap();
+        // Only the last message, the one before the start having been skipped
+        assert_eq!(shown, 1);
+    }
+}
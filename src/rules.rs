@@ -0,0 +1,466 @@
+struct Rate {
+    messages: usize,
+    seconds: f64,
+}
+
+enum Action {
+    /// POST the match as JSON to a URL
+    Webhook(String),
+    /// Run a shell command, with the match passed through `CHAT_READER_*` environment variables
+    Command(String),
+    /// Show a desktop notification through `notify-send`
+    Notify,
+}
+
+/// Conditions that are set must all hold for a message to match. When a rate is given, the rule
+/// only matches once more than that many matching messages arrive within the window.
+struct Rule {
+    name: String,
+    pattern: Option<regex::Regex>,
+    user: Option<String>,
+    rate: Option<Rate>,
+    debounce: f64,
+    actions: Vec<Action>,
+}
+
+/// A match of a rule, sent to the thread that runs its actions
+struct Firing {
+    rule: usize,
+    channel: String,
+    user: String,
+    body: String,
+    message: serde_json::Value,
+}
+
+#[derive(Default)]
+struct RuleState {
+    recent: std::collections::VecDeque<f64>,
+    last_fired: Option<f64>,
+}
+
+/// The index of a rule, the channel and, for VOD chat, the ID of the VOD
+type StateKey = (usize, String, Option<String>);
+
+/// Runs the rules from the config file over chat messages. Rate windows and debouncing are kept
+/// per channel for live chat, using send times. VOD chat is kept per VOD instead, using its
+/// timestamps, since several VODs of a channel are read at once and their chat interleaves; this
+/// way archived chat behaves the same as live chat. Actions run on a separate thread so that a
+/// slow webhook or command never holds up reading chat; dropping the engine waits for them to
+/// finish.
+pub struct Engine {
+    rules: std::sync::Arc<Vec<Rule>>,
+    state: std::sync::Mutex<std::collections::HashMap<StateKey, RuleState>>,
+    sender: Option<std::sync::mpsc::Sender<Firing>>,
+    worker: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Rule {
+    fn from_json(json: &serde_json::Value) -> Result<Self, Box<dyn std::error::Error>> {
+        let name = json
+            .get("name")
+            .ok_or("Missing rule name")?
+            .as_str()
+            .ok_or("Could not convert name -> str")?
+            .to_owned();
+        let pattern = match json.get("pattern").and_then(|p| p.as_str()) {
+            Some(pattern) => Some(regex::Regex::new(pattern)?),
+            None => None,
+        };
+        let user = json
+            .get("user")
+            .and_then(|u| u.as_str())
+            .map(|u| u.to_lowercase());
+        let rate = match json.get("rate") {
+            Some(rate) => Some(Rate {
+                messages: rate
+                    .get("messages")
+                    .ok_or("Missing rate messages")?
+                    .as_u64()
+                    .ok_or("Could not convert messages -> u64")? as usize,
+                seconds: rate.get("seconds").and_then(|s| s.as_f64()).unwrap_or(60.0),
+            }),
+            None => None,
+        };
+        let debounce = json
+            .get("debounce")
+            .and_then(|d| d.as_f64())
+            .unwrap_or(60.0);
+        let actions = json
+            .get("actions")
+            .ok_or("Missing rule actions")?
+            .as_array()
+            .ok_or("Could not convert actions -> array")?
+            .iter()
+            .map(|action| -> Result<Action, Box<dyn std::error::Error>> {
+                if let Some(url) = action.get("webhook").and_then(|w| w.as_str()) {
+                    Ok(Action::Webhook(url.to_owned()))
+                } else if let Some(command) = action.get("command").and_then(|c| c.as_str()) {
+                    Ok(Action::Command(command.to_owned()))
+                } else if action.get("notify").is_some() {
+                    Ok(Action::Notify)
+                } else {
+                    Err(format!("Unknown action in rule {}", name).into())
+                }
+            })
+            .collect::<Result<Vec<Action>, Box<dyn std::error::Error>>>()?;
+
+        Ok(Self {
+            name,
+            pattern,
+            user,
+            rate,
+            debounce,
+            actions,
+        })
+    }
+
+    fn matches(&self, message: &crate::common::Message) -> bool {
+        if let Some(pattern) = &self.pattern {
+            if !pattern.is_match(&message.body) {
+                return false;
+            }
+        }
+        if let Some(user) = &self.user {
+            match &message.user {
+                Some(name) if name.to_lowercase() == *user => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    fn fire(&self, firing: &Firing) {
+        for action in &self.actions {
+            let result: Result<(), Box<dyn std::error::Error>> = match action {
+                Action::Webhook(url) => crate::common::CLIENT
+                    .post(url)
+                    .json(&serde_json::json!({
+                        "rule": self.name,
+                        "channel": firing.channel,
+                        "message": firing.message
+                    }))
+                    .send()
+                    .and_then(|response| response.error_for_status())
+                    .map(|_| ())
+                    .map_err(|e| e.into()),
+                Action::Command(command) => std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("CHAT_READER_RULE", &self.name)
+                    .env("CHAT_READER_CHANNEL", &firing.channel)
+                    .env("CHAT_READER_USER", &firing.user)
+                    .env("CHAT_READER_BODY", &firing.body)
+                    .status()
+                    .map(|_| ())
+                    .map_err(|e| e.into()),
+                Action::Notify => std::process::Command::new("notify-send")
+                    .arg(format!("chat_reader: {}", self.name))
+                    .arg(format!(
+                        "{}: [{}] {}",
+                        firing.channel, firing.user, firing.body
+                    ))
+                    .status()
+                    .map(|_| ())
+                    .map_err(|e| e.into()),
+            };
+            if let Err(e) = result {
+                eprintln!("Rule {} failed: {}", self.name, e);
+            }
+        }
+    }
+}
+
+impl Engine {
+    pub fn from_config(config: &serde_json::Value) -> Result<Self, Box<dyn std::error::Error>> {
+        let rules = config
+            .get("rules")
+            .ok_or("Missing rules in config")?
+            .as_array()
+            .ok_or("Could not convert rules -> array")?
+            .iter()
+            .map(Rule::from_json)
+            .collect::<Result<Vec<Rule>, Box<dyn std::error::Error>>>()?;
+        let rules = std::sync::Arc::new(rules);
+        let (sender, receiver) = std::sync::mpsc::channel::<Firing>();
+        let worker = {
+            let rules = rules.clone();
+            std::thread::spawn(move || {
+                for firing in receiver {
+                    rules[firing.rule].fire(&firing);
+                }
+            })
+        };
+        Ok(Self {
+            rules,
+            state: std::sync::Mutex::new(std::collections::HashMap::new()),
+            sender: Some(sender),
+            worker: Some(worker),
+        })
+    }
+
+    /// Runs the rules over a message from `channel`, either live or from the VOD with the ID `vod`
+    pub fn process(&self, channel: &str, vod: Option<&str>, message: &crate::common::Message) {
+        let time = match vod {
+            Some(_) => message.timestamp.or(message.sent),
+            None => message.sent.or(message.timestamp),
+        }
+        .unwrap_or_default();
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !rule.matches(message) {
+                continue;
+            }
+
+            {
+                let mut states = self.state.lock().unwrap();
+                let state = states
+                    .entry((index, channel.to_owned(), vod.map(String::from)))
+                    .or_default();
+
+                if let Some(rate) = &rule.rate {
+                    state.recent.push_back(time);
+                    while let Some(first) = state.recent.front() {
+                        if time - *first > rate.seconds {
+                            state.recent.pop_front();
+                        } else {
+                            break;
+                        }
+                    }
+                    if state.recent.len() <= rate.messages {
+                        continue;
+                    }
+                }
+
+                match state.last_fired {
+                    Some(last) if time >= last && time - last < rule.debounce => continue,
+                    _ => state.last_fired = Some(time),
+                }
+            }
+
+            let firing = Firing {
+                rule: index,
+                channel: channel.to_owned(),
+                user: message.user.clone().unwrap_or_default(),
+                body: message.body.clone(),
+                message: message.to_json(),
+            };
+            if let Some(sender) = &self.sender {
+                if sender.send(firing).is_err() {
+                    eprintln!("Rule {} failed: the action thread has stopped", rule.name);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        // Closing the channel lets the worker finish the actions that are still queued
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                eprintln!("The action thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Read, Write};
+
+    /// A local stand-in for a webhook endpoint that answers `count` requests after `delay`,
+    /// returning their JSON bodies
+    fn webhook(
+        count: usize,
+        delay: std::time::Duration,
+    ) -> (String, std::thread::JoinHandle<Vec<serde_json::Value>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            (0..count)
+                .map(|_| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    std::thread::sleep(delay);
+                    let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                    let mut length = 0;
+                    let mut line = String::new();
+                    while line != "\r\n" {
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                        if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    stream
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        )
+                        .unwrap();
+                    serde_json::from_slice(&body).unwrap()
+                })
+                .collect()
+        });
+        (url, handle)
+    }
+
+    fn message(user: &str, body: &str, timestamp: f64) -> crate::common::Message {
+        crate::common::Message {
+            user: Some(user.to_owned()),
+            body: body.to_owned(),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn debounces_per_channel() {
+        let (url, hook) = webhook(3, std::time::Duration::ZERO);
+        let engine = Engine::from_config(&serde_json::json!({
+            "rules": [{
+                "name": "hype",
+                "pattern": "(?i)hype",
+                "debounce": 60,
+                "actions": [{ "webhook": url }]
+            }]
+        }))
+        .unwrap();
+
+        engine.process("first", None, &message("a", "HYPE", 0.0));
+        engine.process("first", None, &message("b", "hype", 10.0));
+        engine.process("second", None, &message("c", "hype", 10.0));
+        engine.process("first", None, &message("d", "calm", 80.0));
+        engine.process("first", None, &message("e", "hype", 90.0));
+        drop(engine);
+
+        let fired: Vec<(String, String)> = hook
+            .join()
+            .unwrap()
+            .iter()
+            .map(|request| {
+                assert_eq!(request["rule"], "hype");
+                (
+                    request["channel"].as_str().unwrap().to_owned(),
+                    request["message"]["user"].as_str().unwrap().to_owned(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            fired,
+            [
+                (String::from("first"), String::from("a")),
+                (String::from("second"), String::from("c")),
+                (String::from("first"), String::from("e")),
+            ]
+        );
+    }
+
+    #[test]
+    fn fires_on_rate_and_user() {
+        let (url, hook) = webhook(2, std::time::Duration::ZERO);
+        let engine = Engine::from_config(&serde_json::json!({
+            "rules": [
+                {
+                    "name": "flood",
+                    "rate": { "messages": 2, "seconds": 10 },
+                    "debounce": 0,
+                    "actions": [{ "webhook": url }]
+                },
+                { "name": "streamer", "user": "Streamer", "actions": [{ "webhook": url }] }
+            ]
+        }))
+        .unwrap();
+
+        // Only the third message within ten seconds exceeds the rate
+        engine.process("channel", None, &message("a", "one", 0.0));
+        engine.process("channel", None, &message("b", "two", 5.0));
+        engine.process("channel", None, &message("c", "three", 20.0));
+        engine.process("channel", None, &message("d", "four", 21.0));
+        engine.process("channel", None, &message("e", "five", 22.0));
+        engine.process("channel", None, &message("streamer", "hello", 40.0));
+        drop(engine);
+
+        let fired: Vec<(String, String)> = hook
+            .join()
+            .unwrap()
+            .iter()
+            .map(|request| {
+                (
+                    request["rule"].as_str().unwrap().to_owned(),
+                    request["message"]["body"].as_str().unwrap().to_owned(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            fired,
+            [
+                (String::from("flood"), String::from("five")),
+                (String::from("streamer"), String::from("hello")),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_interleaved_vods_apart() {
+        let (url, hook) = webhook(3, std::time::Duration::ZERO);
+        let engine = Engine::from_config(&serde_json::json!({
+            "rules": [
+                { "name": "hype", "pattern": "hype", "debounce": 60, "actions": [{ "webhook": url }] },
+                {
+                    "name": "flood",
+                    "pattern": "spam",
+                    "rate": { "messages": 2, "seconds": 10 },
+                    "debounce": 0,
+                    "actions": [{ "webhook": url }]
+                }
+            ]
+        }))
+        .unwrap();
+
+        // Two VODs of the same channel read at once, so that their timestamps interleave
+        let first = Some("1");
+        let second = Some("2");
+        engine.process("channel", first, &message("a", "hype", 100.0));
+        engine.process("channel", second, &message("b", "hype", 5.0));
+        engine.process("channel", first, &message("c", "hype", 110.0));
+        engine.process("channel", second, &message("d", "hype", 20.0));
+        engine.process("channel", second, &message("e", "spam", 500.0));
+        engine.process("channel", first, &message("f", "spam", 0.0));
+        engine.process("channel", second, &message("g", "spam", 501.0));
+        engine.process("channel", first, &message("h", "spam", 1.0));
+        engine.process("channel", first, &message("i", "spam", 2.0));
+        drop(engine);
+
+        let fired: Vec<String> = hook
+            .join()
+            .unwrap()
+            .iter()
+            .map(|request| {
+                assert_eq!(request["channel"], "channel");
+                request["message"]["user"].as_str().unwrap().to_owned()
+            })
+            .collect();
+        // Each VOD fires the debounced rule once, and only the first VOD exceeds the rate
+        assert_eq!(fired, ["a", "b", "i"]);
+    }
+
+    #[test]
+    fn slow_actions_do_not_block() {
+        let (url, hook) = webhook(1, std::time::Duration::from_secs(2));
+        let engine = Engine::from_config(&serde_json::json!({
+            "rules": [{ "name": "any", "actions": [{ "webhook": url }] }]
+        }))
+        .unwrap();
+
+        let started = std::time::Instant::now();
+        engine.process("channel", None, &message("a", "hello", 0.0));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+        // Dropping the engine waits for the queued webhook to be delivered
+        drop(engine);
+        assert!(started.elapsed() >= std::time::Duration::from_secs(2));
+        assert_eq!(hook.join().unwrap().len(), 1);
+    }
+}
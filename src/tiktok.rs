@@ -36,7 +36,7 @@ impl Iterator for CaptionIterator {
     type Item = Vec<crate::common::Message>;
     fn next(&mut self) -> Option<Self::Item> {
         let response = crate::common::CLIENT
-            .get(&format!("https://www.tiktok.com/@tiktok/video/{}", self.id))
+            .get(format!("https://www.tiktok.com/@tiktok/video/{}", self.id))
             .header(reqwest::header::USER_AGENT, crate::common::USER_AGENT)
             .send()
             .unwrap()
@@ -60,7 +60,7 @@ impl Iterator for ChatIterator {
 
     fn next(&mut self) -> Option<Self::Item> {
         let response: serde_json::Value = crate::common::CLIENT
-            .get(&format!(
+            .get(format!(
                 "https://us.tiktok.com/api/comment/list/?aweme_id={}&count=50&cursor={}",
                 self.id, self.cursor
             ))
@@ -90,6 +90,7 @@ impl Iterator for ChatIterator {
                         timestamp: None,
                         user,
                         body,
+                        ..Default::default()
                     })
                 })
                 .collect(),
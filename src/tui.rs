@@ -0,0 +1,608 @@
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use hhmmss::Hhmmss;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Sparkline};
+use std::io::Write;
+
+type Terminal = ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>;
+
+const HELP: &str =
+    "q quit | tab switch pane | enter open VOD | / filter | g jump to offset | y copy link";
+
+enum Input {
+    Normal,
+    /// Editing the filter, which is applied as it is typed and restored on escape
+    Filter {
+        text: String,
+        original: String,
+    },
+    /// Typing an offset to jump to
+    Jump(String),
+}
+
+#[derive(PartialEq, Eq)]
+enum Focus {
+    Vods,
+    Chat,
+}
+
+/// Parses an offset such as "1:02:03", "1h2m3s", "90m" or "3723" into seconds
+fn parse_offset(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.contains(':') {
+        return text.split(':').try_fold(0.0, |total, part| {
+            Some(total * 60.0 + part.parse::<f64>().ok()?)
+        });
+    }
+    if let Ok(seconds) = text.parse() {
+        return Some(seconds);
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    for c in text.chars() {
+        let unit = match c {
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => {
+                number.push(c);
+                continue;
+            }
+        };
+        total += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(total)
+}
+
+struct App<'a, V> {
+    vods: &'a [V],
+    vod_state: ListState,
+    /// The VOD whose chat is shown
+    open: Option<usize>,
+    receiver: Option<std::sync::mpsc::Receiver<Vec<crate::common::Message>>>,
+    messages: Vec<crate::common::Message>,
+    filter: regex::Regex,
+    /// Indices of the messages that match the filter
+    matches: Vec<usize>,
+    /// The selected match, and the first match shown in the chat pane
+    selected: usize,
+    scroll: usize,
+    page: usize,
+    focus: Focus,
+    input: Input,
+    status: String,
+}
+
+impl<'a, V> App<'a, V>
+where
+    V: crate::common::Vod,
+{
+    fn new(vods: &'a [V], filter: regex::Regex) -> Self {
+        let mut vod_state = ListState::default();
+        if !vods.is_empty() {
+            vod_state.select(Some(0));
+        }
+        Self {
+            vods,
+            vod_state,
+            open: None,
+            receiver: None,
+            messages: Vec::new(),
+            filter,
+            matches: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            page: 1,
+            focus: Focus::Vods,
+            input: Input::Normal,
+            status: String::from(HELP),
+        }
+    }
+
+    fn timestamp(&self, index: usize) -> f64 {
+        self.messages[index].timestamp.unwrap_or_default()
+    }
+
+    /// Starts loading the chat of the highlighted VOD in the background
+    fn load(&mut self) {
+        let index = match self.vod_state.selected() {
+            Some(index) => index,
+            None => return,
+        };
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let comments = self.vods[index].comments();
+        std::thread::spawn(move || {
+            for messages in comments {
+                if sender.send(messages).is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.open = Some(index);
+        self.receiver = Some(receiver);
+        self.messages.clear();
+        self.matches.clear();
+        self.selected = 0;
+        self.scroll = 0;
+        self.focus = Focus::Chat;
+    }
+
+    /// Takes in any chat that has finished loading
+    fn receive(&mut self) {
+        let receiver = match &self.receiver {
+            Some(receiver) => receiver,
+            None => return,
+        };
+        let mut finished = false;
+        loop {
+            match receiver.try_recv() {
+                Ok(messages) => {
+                    for message in messages {
+                        if message.is_match(&self.filter) {
+                            self.matches.push(self.messages.len());
+                        }
+                        self.messages.push(message);
+                    }
+                }
+                Err(std::sync::mpsc::TryRecvError::Empty) => break,
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    finished = true;
+                    break;
+                }
+            }
+        }
+        if finished {
+            self.receiver = None;
+        }
+    }
+
+    /// Applies a new filter, keeping the selection at about the same point in the VOD
+    fn set_filter(&mut self, pattern: &str) {
+        let filter = match regex::Regex::new(pattern) {
+            Ok(filter) => filter,
+            Err(e) => {
+                self.status = e.to_string().lines().last().unwrap_or_default().to_owned();
+                return;
+            }
+        };
+        let position = self
+            .matches
+            .get(self.selected)
+            .map(|&index| self.timestamp(index))
+            .unwrap_or_default();
+        self.filter = filter;
+        self.matches = (0..self.messages.len())
+            .filter(|&index| self.messages[index].is_match(&self.filter))
+            .collect();
+        self.select_offset(position);
+        self.status = format!("{} matching messages", self.matches.len());
+    }
+
+    /// Selects the first match at or after `offset` seconds
+    fn select_offset(&mut self, offset: f64) {
+        self.selected = self
+            .matches
+            .partition_point(|&index| self.timestamp(index) < offset)
+            .min(self.matches.len().saturating_sub(1));
+    }
+
+    /// Copies a link to the selected message's point in the VOD, through the terminal's OSC 52
+    /// clipboard support
+    fn copy_link(&mut self) {
+        let (open, index) = match (self.open, self.matches.get(self.selected)) {
+            (Some(open), Some(&index)) => (open, index),
+            _ => return,
+        };
+        let link = self.vods[open].link(self.timestamp(index));
+        let mut stdout = std::io::stdout();
+        let copied = write!(
+            stdout,
+            "\x1b]52;c;{}\x07",
+            crate::render::base64(link.as_bytes())
+        )
+        .and_then(|_| stdout.flush());
+        self.status = match copied {
+            Ok(_) => format!("Copied {}", link),
+            Err(e) => format!("Unable to copy {}: {}", link, e),
+        };
+    }
+
+    /// Number of matching messages sent each minute, grouped so that they fit in `width` bars,
+    /// along with the minutes per bar
+    fn activity(&self, width: usize) -> (Vec<u64>, usize) {
+        let last = self
+            .matches
+            .iter()
+            .map(|&index| self.timestamp(index))
+            .fold(0.0, f64::max);
+        let minutes = last as usize / 60 + 1;
+        let per_bar = minutes.div_ceil(width.max(1));
+        let mut bars = vec![0; minutes.div_ceil(per_bar)];
+        for &index in &self.matches {
+            bars[self.timestamp(index) as usize / 60 / per_bar] += 1;
+        }
+        (bars, per_bar)
+    }
+
+    fn message_line(&self, index: usize, selected: bool) -> Line<'static> {
+        let message = &self.messages[index];
+        let style = if selected {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default()
+        };
+        let timestamp = std::time::Duration::from_secs(self.timestamp(index) as u64).hhmmss();
+        let mut spans = vec![Span::styled(
+            format!("[{}]", timestamp),
+            style.fg(Color::DarkGray),
+        )];
+        if let Some(user) = &message.user {
+            let color = match message.color {
+                Some((r, g, b)) => Color::Rgb(r, g, b),
+                None => Color::Cyan,
+            };
+            spans.push(Span::styled(format!("[{}]", user), style.fg(color)));
+        }
+        if let Some(event) = &message.event {
+            spans.push(Span::styled(format!(" {}", event), style.fg(Color::Yellow)));
+        }
+        spans.push(Span::styled(format!(" {}", message.body), style));
+        Line::from(spans)
+    }
+
+    fn draw(&mut self, frame: &mut ratatui::Frame) {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
+            .split(frame.size());
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(5),
+                Constraint::Min(3),
+                Constraint::Length(1),
+            ])
+            .split(columns[1]);
+
+        let border = |focus: Focus| {
+            if self.focus == focus {
+                Style::default().fg(Color::Green)
+            } else {
+                Style::default()
+            }
+        };
+
+        let vods: Vec<ListItem> = self
+            .vods
+            .iter()
+            .map(|vod| {
+                ListItem::new(
+                    vod.to_string()
+                        .lines()
+                        .next()
+                        .unwrap_or_default()
+                        .to_owned(),
+                )
+            })
+            .collect();
+        let vods = List::new(vods)
+            .block(
+                Block::default()
+                    .title("VODs")
+                    .borders(Borders::ALL)
+                    .border_style(border(Focus::Vods)),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(vods, columns[0], &mut self.vod_state);
+
+        let (bars, per_bar) = self.activity(rows[0].width.saturating_sub(2) as usize);
+        let activity = Sparkline::default()
+            .block(
+                Block::default()
+                    .title(format!("Activity (matches per {} min)", per_bar))
+                    .borders(Borders::ALL),
+            )
+            .data(&bars)
+            .style(Style::default().fg(Color::Yellow));
+        frame.render_widget(activity, rows[0]);
+
+        // Keep the selected message on screen
+        self.page = rows[1].height.saturating_sub(2).max(1) as usize;
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.page {
+            self.scroll = self.selected + 1 - self.page;
+        }
+        let end = (self.scroll + self.page).min(self.matches.len());
+        let lines: Vec<Line> = (self.scroll..end)
+            .map(|i| self.message_line(self.matches[i], i == self.selected))
+            .collect();
+        let mut title = match self.open {
+            Some(open) => self.vods[open]
+                .to_string()
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+            None => String::from("Chat"),
+        };
+        title.push_str(&format!(
+            " ({}/{} messages{})",
+            self.matches.len(),
+            self.messages.len(),
+            if self.receiver.is_some() {
+                ", loading"
+            } else {
+                ""
+            }
+        ));
+        let chat = Paragraph::new(lines).block(
+            Block::default()
+                .title(title)
+                .borders(Borders::ALL)
+                .border_style(border(Focus::Chat)),
+        );
+        frame.render_widget(chat, rows[1]);
+
+        let status = match &self.input {
+            Input::Normal => self.status.to_owned(),
+            Input::Filter { text, .. } => format!("/{}", text),
+            Input::Jump(text) => format!("Jump to: {}", text),
+        };
+        frame.render_widget(Paragraph::new(status), rows[2]);
+    }
+
+    /// Handles a key press, returning whether to quit
+    fn handle_key(&mut self, key: crossterm::event::KeyEvent) -> bool {
+        match std::mem::replace(&mut self.input, Input::Normal) {
+            Input::Filter { mut text, original } => {
+                match key.code {
+                    KeyCode::Enter => return false,
+                    KeyCode::Esc => {
+                        self.set_filter(&original);
+                        return false;
+                    }
+                    KeyCode::Backspace => {
+                        text.pop();
+                    }
+                    KeyCode::Char(c) => text.push(c),
+                    _ => {}
+                }
+                self.set_filter(&text);
+                self.input = Input::Filter { text, original };
+                return false;
+            }
+            Input::Jump(mut text) => {
+                match key.code {
+                    KeyCode::Enter => match parse_offset(&text) {
+                        Some(offset) => {
+                            self.select_offset(offset);
+                            self.focus = Focus::Chat;
+                            self.status = format!(
+                                "Jumped to {}",
+                                std::time::Duration::from_secs(offset as u64).hhmmss()
+                            );
+                        }
+                        None => self.status = format!("Invalid offset {}", text),
+                    },
+                    KeyCode::Esc => {}
+                    KeyCode::Backspace => {
+                        text.pop();
+                        self.input = Input::Jump(text);
+                    }
+                    KeyCode::Char(c) => {
+                        text.push(c);
+                        self.input = Input::Jump(text);
+                    }
+                    _ => self.input = Input::Jump(text),
+                }
+                return false;
+            }
+            Input::Normal => {}
+        }
+
+        let last = self.matches.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Char('q') => return true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Vods => Focus::Chat,
+                    Focus::Chat => Focus::Vods,
+                }
+            }
+            KeyCode::Char('/') => {
+                let original = self.filter.as_str().to_owned();
+                self.input = Input::Filter {
+                    text: original.to_owned(),
+                    original,
+                };
+            }
+            KeyCode::Char('g') => self.input = Input::Jump(String::new()),
+            KeyCode::Char('y') => self.copy_link(),
+            KeyCode::Enter if self.focus == Focus::Vods => self.load(),
+            KeyCode::Up | KeyCode::Char('k') => match self.focus {
+                Focus::Vods => {
+                    let selected = self.vod_state.selected().unwrap_or_default();
+                    self.vod_state.select(Some(selected.saturating_sub(1)));
+                }
+                Focus::Chat => self.selected = self.selected.saturating_sub(1),
+            },
+            KeyCode::Down | KeyCode::Char('j') => match self.focus {
+                Focus::Vods => {
+                    let selected = self.vod_state.selected().unwrap_or_default();
+                    let last = self.vods.len().saturating_sub(1);
+                    self.vod_state.select(Some((selected + 1).min(last)));
+                }
+                Focus::Chat => self.selected = (self.selected + 1).min(last),
+            },
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(self.page),
+            KeyCode::PageDown => self.selected = (self.selected + self.page).min(last),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = last,
+            _ => {}
+        }
+        false
+    }
+
+    fn run(&mut self, terminal: &mut Terminal) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            self.receive();
+            terminal.draw(|frame| self.draw(frame))?;
+            if crossterm::event::poll(std::time::Duration::from_millis(100))? {
+                if let Event::Key(key) = crossterm::event::read()? {
+                    if key.kind == KeyEventKind::Press && self.handle_key(key) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Puts the terminal back into its normal mode, as far as that is possible
+fn restore() {
+    let _ = crossterm::terminal::disable_raw_mode();
+    let _ = crossterm::execute!(
+        std::io::stdout(),
+        crossterm::terminal::LeaveAlternateScreen,
+        crossterm::cursor::Show
+    );
+}
+
+/// Restores the terminal when dropped, including while unwinding from a panic
+struct RestoreGuard;
+
+impl Drop for RestoreGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Browses the chat of `vods` in a terminal UI, starting with `filter`
+pub fn run<V>(vods: &[V], filter: &regex::Regex) -> Result<(), Box<dyn std::error::Error>>
+where
+    V: crate::common::Vod,
+{
+    // Restore the terminal before a panic message is printed, so that it can be read
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        hook(info);
+    }));
+
+    crossterm::terminal::enable_raw_mode()?;
+    let _guard = RestoreGuard;
+    let mut stdout = std::io::stdout();
+    crossterm::execute!(stdout, crossterm::terminal::EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(ratatui::backend::CrosstermBackend::new(stdout))?;
+
+    App::new(vods, filter.clone()).run(&mut terminal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset("3723"), Some(3723.0));
+        assert_eq!(parse_offset(" 90.5 "), Some(90.5));
+        assert_eq!(parse_offset("1:02:03"), Some(3723.0));
+        assert_eq!(parse_offset("2:30"), Some(150.0));
+        assert_eq!(parse_offset("1h2m3s"), Some(3723.0));
+        assert_eq!(parse_offset("90m"), Some(5400.0));
+        assert_eq!(parse_offset("1h30"), None);
+        assert_eq!(parse_offset("1:xx"), None);
+        assert_eq!(parse_offset("soon"), None);
+    }
+
+    /// Chat with messages at fixed offsets
+    struct StubChat(Option<Vec<crate::common::Message>>);
+
+    impl crate::common::ChatIterator for StubChat {}
+    impl Iterator for StubChat {
+        type Item = Vec<crate::common::Message>;
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.take()
+        }
+    }
+
+    struct StubVod(Vec<(f64, &'static str)>);
+
+    impl std::fmt::Display for StubVod {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "stub")
+        }
+    }
+
+    impl crate::common::Vod for StubVod {
+        fn id(&self) -> String {
+            String::from("stub")
+        }
+
+        fn channel(&self) -> String {
+            String::from("stub")
+        }
+
+        fn comments(&self) -> Box<dyn crate::common::ChatIterator> {
+            let messages = self
+                .0
+                .iter()
+                .map(|&(timestamp, body)| crate::common::Message {
+                    body: body.to_owned(),
+                    timestamp: Some(timestamp),
+                    ..Default::default()
+                })
+                .collect();
+            Box::new(StubChat(Some(messages)))
+        }
+
+        fn link(&self, _: f64) -> String {
+            String::new()
+        }
+    }
+
+    /// An app with the chat of the first of `vods` loaded and filtered by `filter`
+    fn app<'a>(vods: &'a [StubVod], filter: &str) -> App<'a, StubVod> {
+        let mut app = App::new(vods, regex::Regex::new(".").unwrap());
+        app.load();
+        while app.receiver.is_some() {
+            app.receive();
+        }
+        app.set_filter(filter);
+        app
+    }
+
+    #[test]
+    fn buckets_activity_by_minute() {
+        let vods = [StubVod(vec![
+            (0.0, "hype"),
+            (30.0, "hype"),
+            (59.0, "calm"),
+            (61.0, "hype"),
+            (300.0, "hype"),
+        ])];
+        let app = app(&vods, "hype");
+        assert_eq!(app.matches.len(), 4);
+        assert_eq!(app.activity(10), (vec![2, 1, 0, 0, 0, 1], 1));
+        // Minutes are grouped so that the bars fit
+        assert_eq!(app.activity(2), (vec![3, 1], 3));
+        assert_eq!(app.activity(0), (vec![4], 6));
+    }
+
+    #[test]
+    fn activity_without_matches() {
+        let vods = [StubVod(vec![(120.0, "calm")])];
+        let app = app(&vods, "hype");
+        assert_eq!(app.activity(10), (vec![0], 1));
+    }
+}
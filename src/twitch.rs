@@ -8,15 +8,305 @@ lazy_static::lazy_static! {
     static ref CLIENT_INTEGRITY: Result<String, std::env::VarError> = std::env::var("CHAT_READER_CLIENT_INTEGRITY");
 }
 
-fn gql(json: &serde_json::Value) -> Result<serde_json::Value, reqwest::Error> {
-    crate::common::CLIENT
-        .post(GQL)
+lazy_static::lazy_static! {
+    /// The GQL endpoint, which can be pointed elsewhere through `twitch.gql` in the config
+    static ref GQL_URL: String = crate::config::CONFIG
+        .get("twitch")
+        .and_then(|t| t.get("gql"))
+        .and_then(|g| g.as_str())
+        .unwrap_or(GQL)
+        .to_owned();
+
+    /// A user OAuth token from `twitch.oauth_token` in the config, which gives access to
+    /// subscriber-only VODs and their chat
+    static ref OAUTH_TOKEN: Option<String> = crate::config::CONFIG
+        .get("twitch")
+        .and_then(|t| t.get("oauth_token"))
+        .and_then(|o| o.as_str())
+        .map(|o| o.trim_start_matches("oauth:").to_owned())
+        .filter(|o| !o.is_empty());
+}
+
+/// The persisted query hash of every GQL operation that is used. Twitch rotates these from time
+/// to time, so each can be overridden through `twitch.persisted_queries` in the config.
+pub const PERSISTED_QUERIES: [(&str, &str); 7] = [
+    (
+        "ClipsCards__Game",
+        "0d8d0eba9fc7ef77de54a7d933998e21ad7a1274c867ec565ac14ffdce77b1f9",
+    ),
+    (
+        "DirectoryPage_Game",
+        "749035333f1837aca1c5bae468a11c39604a91c9206895aa90b4657ab6213c24",
+    ),
+    (
+        "BrowsePage_Popular",
+        "267d2d2a64e0a0d6206c039ea9948d14a9b300a927d52b2efc52d2486ff0ec65",
+    ),
+    (
+        "PlaybackAccessToken",
+        "0828119ded1c13477966434e15800ff57ddacf13ba1911c129dc2200705b0712",
+    ),
+    (
+        "FilterableVideoTower_Videos",
+        "a937f1d22e269e39a03b509f65a7490f9fc247d7f83d6ac1421523e3b68042cb",
+    ),
+    (
+        "ClipsCards__User",
+        "b73ad2bfaecfd30a9e6c28fada15bd97032c83ec77a0440766a56fe0bd632777",
+    ),
+    (
+        "VideoMetadata",
+        "226edb3e692509f727fd56821f5653c05740242c82b0388883e0c0e75dcbf687",
+    ),
+];
+
+pub fn persisted_query_hash(operation: &str) -> String {
+    let configured = crate::config::CONFIG
+        .get("twitch")
+        .and_then(|t| t.get("persisted_queries"))
+        .and_then(|q| q.get(operation))
+        .and_then(|h| h.as_str());
+    match configured {
+        Some(hash) => hash.to_owned(),
+        None => PERSISTED_QUERIES
+            .iter()
+            .find(|(name, _)| *name == operation)
+            .map(|(_, hash)| hash.to_string())
+            .unwrap_or_default(),
+    }
+}
+
+fn persisted_query(operation: &str) -> serde_json::Value {
+    serde_json::json!({
+        "persistedQuery": {
+            "version": 1,
+            "sha256Hash": persisted_query_hash(operation)
+        }
+    })
+}
+
+/// Returned when Twitch no longer recognizes the hash of a persisted query
+#[derive(Debug)]
+pub struct StaleQuery {
+    operation: String,
+}
+
+impl std::fmt::Display for StaleQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The persisted query hash for {} is out of date; override it under twitch.persisted_queries in the config (run `twitch doctor` to check the others)",
+            self.operation
+        )
+    }
+}
+
+impl std::error::Error for StaleQuery {}
+
+/// Returned when content is restricted to subscribers and the configured OAuth token, if any, does
+/// not give access to it
+#[derive(Debug)]
+pub struct AuthRequired {
+    what: String,
+}
+
+impl std::fmt::Display for AuthRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if OAUTH_TOKEN.is_some() {
+            write!(
+                f,
+                "{} is restricted to subscribers, and the account of twitch.oauth_token does not have access",
+                self.what
+            )
+        } else {
+            write!(
+                f,
+                "{} is restricted to subscribers; set twitch.oauth_token in the config to the OAuth token of an account with access",
+                self.what
+            )
+        }
+    }
+}
+
+impl std::error::Error for AuthRequired {}
+
+fn gql(json: &serde_json::Value) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let mut request = crate::common::CLIENT
+        .post(GQL_URL.as_str())
         .header("Client-Id", CLIENT_ID)
         .header("Client-Integrity", CLIENT_INTEGRITY.as_ref().unwrap())
-        .header("X-Device-Id", DEVICE_ID.as_ref().unwrap())
-        .json(json)
-        .send()?
-        .json()
+        .header("X-Device-Id", DEVICE_ID.as_ref().unwrap());
+    if let Some(token) = OAUTH_TOKEN.as_ref() {
+        request = request.header("Authorization", format!("OAuth {}", token));
+    }
+    let response = request.json(json).send()?;
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        return Err(
+            "Twitch rejected the OAuth token; check twitch.oauth_token in the config".into(),
+        );
+    }
+    let response: serde_json::Value = response.json()?;
+
+    let stale = std::iter::once(&response)
+        .chain(response.as_array().into_iter().flatten())
+        .flat_map(|r| r.get("errors").and_then(|e| e.as_array()))
+        .flatten()
+        .any(|e| e.get("message").and_then(|m| m.as_str()) == Some("PersistedQueryNotFound"));
+    if stale {
+        let operation = json
+            .get("operationName")
+            .or_else(|| json.get(0)?.get("operationName"))
+            .and_then(|o| o.as_str())
+            .unwrap_or_default();
+        return Err(Box::new(StaleQuery {
+            operation: operation.to_owned(),
+        }));
+    }
+    Ok(response)
+}
+
+pub enum QueryHealth {
+    Ok,
+    Stale,
+    Error(String),
+}
+
+/// Variables that a persisted query can be sent with to check it against `channel` and one of its
+/// VODs, along with the path under `data` that a working query returns
+fn probe(operation: &str, channel: &str, video: &str) -> (serde_json::Value, &'static str) {
+    let filter = StreamFilter {
+        languages: Vec::new(),
+        sort: StreamSort::Relevance,
+        tags: Vec::new(),
+    };
+    match operation {
+        "ClipsCards__Game" => (
+            serde_json::json!({
+                "gameName": "Just Chatting",
+                "limit": 1,
+                "criteria": { "languages": [], "filter": Recency::LastWeek.as_str() }
+            }),
+            "/game/clips",
+        ),
+        "DirectoryPage_Game" => (
+            serde_json::json!({
+                "imageWidth": 50,
+                "name": "Just Chatting",
+                "options": filter.options(&[]),
+                "freeformTagsEnabled": false,
+                "sortTypeIsRecency": false,
+                "limit": 1
+            }),
+            "/game/streams",
+        ),
+        "BrowsePage_Popular" => (
+            serde_json::json!({
+                "imageWidth": 50,
+                "limit": 1,
+                "platformType": "all",
+                "options": filter.options(&[]),
+                "sortTypeIsRecency": false,
+                "freeformTagsEnabled": true
+            }),
+            "/streams",
+        ),
+        "PlaybackAccessToken" => (
+            serde_json::json!({
+                "isLive": true,
+                "login": channel,
+                "isVod": false,
+                "vodID": "",
+                "playerType": "site"
+            }),
+            "/streamPlaybackAccessToken",
+        ),
+        "FilterableVideoTower_Videos" => (
+            serde_json::json!({
+                "limit": 1,
+                "channelOwnerLogin": channel,
+                "broadcastType": null,
+                "videoSort": "TIME"
+            }),
+            "/user/videos",
+        ),
+        "ClipsCards__User" => (
+            serde_json::json!({
+                "login": channel,
+                "limit": 1,
+                "criteria": { "filter": Recency::AllTime.as_str() }
+            }),
+            "/user/clips",
+        ),
+        "VideoMetadata" => (
+            serde_json::json!({ "channelLogin": channel, "videoID": video }),
+            "/video",
+        ),
+        _ => (serde_json::json!({}), ""),
+    }
+}
+
+/// Sends a persisted query, checking that Twitch both recognizes its hash and returns the data
+/// that the query is used for
+fn check(operation: &str, channel: &str, video: &str) -> (QueryHealth, Option<serde_json::Value>) {
+    let (variables, path) = probe(operation, channel, video);
+    let req_json = serde_json::json!([{
+        "operationName": operation,
+        "variables": variables,
+        "extensions": persisted_query(operation)
+    }]);
+    match gql(&req_json) {
+        Ok(response) => {
+            let data = response
+                .get(0)
+                .and_then(|r| r.get("data"))
+                .and_then(|d| d.pointer(path))
+                .filter(|d| !d.is_null())
+                .cloned();
+            match data {
+                Some(data) => (QueryHealth::Ok, Some(data)),
+                None => (
+                    QueryHealth::Error(format!("Missing data{}", path.replace('/', "."))),
+                    None,
+                ),
+            }
+        }
+        Err(e) if e.is::<StaleQuery>() => (QueryHealth::Stale, None),
+        Err(e) => (QueryHealth::Error(e.to_string()), None),
+    }
+}
+
+/// Sends every persisted query for `channel` and its latest VOD, reporting for each operation
+/// whether Twitch still recognizes its hash and answers with the expected data
+pub fn doctor(channel: &str) -> Vec<(&'static str, String, QueryHealth)> {
+    let (videos, latest) = check("FilterableVideoTower_Videos", channel, "");
+    let video = latest
+        .as_ref()
+        .and_then(|l| l.pointer("/edges/0/node/id"))
+        .and_then(|i| i.as_str())
+        .map(|i| i.to_owned());
+
+    let mut results: Vec<(&'static str, String, QueryHealth)> = PERSISTED_QUERIES
+        .par_iter()
+        .filter(|(operation, _)| *operation != "FilterableVideoTower_Videos")
+        .map(|(operation, _)| {
+            let health = match (operation.starts_with("Video"), &video) {
+                (true, None) => QueryHealth::Error(format!("{} has no VOD to check with", channel)),
+                (_, video) => check(operation, channel, video.as_deref().unwrap_or_default()).0,
+            };
+            (*operation, persisted_query_hash(operation), health)
+        })
+        .collect();
+    results.push((
+        "FilterableVideoTower_Videos",
+        persisted_query_hash("FilterableVideoTower_Videos"),
+        videos,
+    ));
+    results.sort_by_key(|(operation, _, _)| {
+        PERSISTED_QUERIES
+            .iter()
+            .position(|(name, _)| name == operation)
+    });
+    results
 }
 
 #[derive(PartialEq, Eq)]
@@ -57,32 +347,440 @@ impl std::str::FromStr for Recency {
     }
 }
 
-#[derive(Debug)]
+#[derive(PartialEq, Eq)]
+pub enum VideoSort {
+    Time,
+    Views,
+}
+
+impl VideoSort {
+    fn as_str(&self) -> &'static str {
+        match self {
+            VideoSort::Time => "TIME",
+            VideoSort::Views => "VIEWS",
+        }
+    }
+}
+
+impl std::str::FromStr for VideoSort {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "TIME" => Ok(VideoSort::Time),
+            "VIEWS" => Ok(VideoSort::Views),
+            _ => Err(r#"Expected: ["TIME", "VIEWS"]"#),
+        }
+    }
+}
+
+/// Order of live streams in a directory or tag listing
+#[derive(Clone, Copy)]
+pub enum StreamSort {
+    Relevance,
+    Viewers,
+    Recent,
+}
+
+impl StreamSort {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StreamSort::Relevance => "RELEVANCE",
+            StreamSort::Viewers => "VIEWER_COUNT",
+            StreamSort::Recent => "RECENT",
+        }
+    }
+}
+
+impl std::str::FromStr for StreamSort {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "RELEVANCE" => Ok(StreamSort::Relevance),
+            "VIEWERS" => Ok(StreamSort::Viewers),
+            "RECENT" => Ok(StreamSort::Recent),
+            _ => Err(r#"Expected: ["RELEVANCE", "VIEWERS", "RECENT"]"#),
+        }
+    }
+}
+
+/// Which live streams to list, and in what order
+pub struct StreamFilter {
+    pub languages: Vec<String>,
+    pub sort: StreamSort,
+    pub tags: Vec<String>,
+}
+
+impl StreamFilter {
+    fn options(&self, tags: &[String]) -> serde_json::Value {
+        let freeform_tags = if tags.is_empty() { None } else { Some(tags) };
+        serde_json::json!({
+            "sort": self.sort.as_str(),
+            "broadcasterLanguages": self.languages,
+            "freeformTags": freeform_tags,
+            "recommendationsContext": {
+                "platform": "web"
+            },
+            "requestID": "JIRA-VXP-2397",
+            "tags": []
+        })
+    }
+}
+
+/// The channels on one page of live streams, skipping any that were on an earlier page, along with
+/// the cursor of the next page if there is one
+fn stream_page(
+    streams: &serde_json::Value,
+    seen: &mut std::collections::HashSet<String>,
+) -> Result<(Vec<Channel>, Option<String>), Box<dyn std::error::Error>> {
+    let edges = streams
+        .get("edges")
+        .ok_or("Missing edges")?
+        .as_array()
+        .ok_or("Unable to convert edges -> array")?;
+    let channels = edges
+        .iter()
+        .flat_map(|edge| edge.get("node")?.get("broadcaster")?.get("login")?.as_str())
+        .filter(|login| seen.insert(login.to_string()))
+        .map(Channel::new)
+        .collect();
+
+    let has_next_page = streams
+        .get("pageInfo")
+        .and_then(|p| p.get("hasNextPage"))
+        .and_then(|h| h.as_bool())
+        .unwrap_or(!edges.is_empty());
+    let cursor = if has_next_page {
+        edges
+            .last()
+            .and_then(|edge| edge.get("cursor")?.as_str())
+            .filter(|cursor| !cursor.is_empty())
+            .map(String::from)
+    } else {
+        None
+    };
+    Ok((channels, cursor))
+}
+
+/// A category (game) on Twitch
+#[derive(Debug, Clone)]
 pub struct Directory {
-    name: String,
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+    pub slug: String,
+}
+
+impl std::fmt::Display for Directory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}) [{}]", self.display_name, self.slug, self.id)
+    }
+}
+
+/// The slug Twitch uses in category URLs, e.g. "Pokémon Legends: Arceus" -> "pokemon-legends-arceus".
+/// Accented Latin letters are written without their accents, apostrophes are dropped and any
+/// other character separates words.
+fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    let mut separated = true;
+    for c in name.chars() {
+        let folded = match c {
+            '\'' | '\u{2019}' => continue,
+            'à'..='å' | 'À'..='Å' => 'a',
+            'ç' | 'Ç' => 'c',
+            'è'..='ë' | 'È'..='Ë' => 'e',
+            'ì'..='ï' | 'Ì'..='Ï' => 'i',
+            'ñ' | 'Ñ' => 'n',
+            'ò'..='ö' | 'ø' | 'Ò'..='Ö' | 'Ø' => 'o',
+            'ù'..='ü' | 'Ù'..='Ü' => 'u',
+            'ý' | 'ÿ' | 'Ý' => 'y',
+            c if c.is_ascii_alphanumeric() => c.to_ascii_lowercase(),
+            _ => {
+                separated = true;
+                continue;
+            }
+        };
+        if separated && !slug.is_empty() {
+            slug.push('-');
+        }
+        separated = false;
+        slug.push(folded);
+    }
+    slug
+}
+
+/// The number of single character edits between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Picks the one slug that `query` is close to, either within a few typos or as the only slug
+/// containing every word of the query
+fn closest<'a>(query: &str, slugs: &[&'a str]) -> Option<&'a str> {
+    let query = slugify(query);
+    if query.is_empty() {
+        return None;
+    }
+    let tolerance = (query.len() / 4).max(1);
+    let mut typos: Vec<(usize, &str)> = slugs
+        .iter()
+        .map(|slug| (edit_distance(&query, slug), *slug))
+        .filter(|(distance, _)| *distance <= tolerance)
+        .collect();
+    typos.sort_by_key(|(distance, _)| *distance);
+    match typos[..] {
+        [(_, slug)] => return Some(slug),
+        [(best, slug), (next, _), ..] if best < next => return Some(slug),
+        _ => {}
+    }
+
+    let containing: Vec<&str> = slugs
+        .iter()
+        .filter(|slug| {
+            let words: Vec<&str> = slug.split('-').collect();
+            query.split('-').all(|word| words.contains(&word))
+        })
+        .copied()
+        .collect();
+    match containing[..] {
+        [slug] => Some(slug),
+        _ => None,
+    }
 }
 
 impl Directory {
-    pub fn new<S>(name: S) -> Self
-    where
-        S: Into<String>,
-    {
-        Self { name: name.into() }
+    /// Resolves a category name, slug or ID to the canonical category. A near miss resolves to the
+    /// category it is close to, otherwise similarly named categories are suggested
+    pub fn resolve(query: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let query = query.trim();
+        let id = if !query.is_empty() && query.chars().all(|c| c.is_ascii_digit()) {
+            Some(query)
+        } else {
+            None
+        };
+        let req_json = serde_json::json!({
+            "query": "query($id: ID, $name: String, $slug: String) { byId: game(id: $id) { id name displayName slug } byName: game(name: $name) { id name displayName slug } bySlug: game(slug: $slug) { id name displayName slug } }",
+            "variables": {
+                "id": id,
+                "name": query,
+                "slug": slugify(query)
+            }
+        });
+        let response = gql(&req_json)?;
+        let data = response.get("data").ok_or("Missing data")?;
+
+        if let Some(game) = ["byId", "byName", "bySlug"]
+            .iter()
+            .flat_map(|alias| data.get(alias))
+            .find(|game| !game.is_null())
+        {
+            return Self::from_json(game);
+        }
+
+        // Fall back to searching, taking the result that the query is a near miss for
+        let candidates = Self::search(query).unwrap_or_default();
+        let slugs: Vec<&str> = candidates.iter().map(|c| c.slug.as_str()).collect();
+        if let Some(slug) = closest(query, &slugs) {
+            if let Some(directory) = candidates.iter().find(|c| c.slug == slug) {
+                return Ok(directory.clone());
+            }
+        }
+
+        if candidates.is_empty() {
+            Err(format!("The directory {} does not exist", query).into())
+        } else {
+            let suggestions: Vec<String> = candidates
+                .iter()
+                .map(|c| format!("\"{}\"", c.display_name))
+                .collect();
+            Err(format!(
+                "The directory {} does not exist, did you mean: {}?",
+                query,
+                suggestions.join(", ")
+            )
+            .into())
+        }
     }
 
-    pub fn channels(&self) -> DirectoryIterator {
+    fn from_json(game: &serde_json::Value) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = |key: &str| -> Result<String, Box<dyn std::error::Error>> {
+            Ok(game
+                .get(key)
+                .and_then(|v| v.as_str())
+                .ok_or(format!("Missing {}", key))?
+                .to_owned())
+        };
+        Ok(Self {
+            id: text("id")?,
+            name: text("name")?,
+            display_name: text("displayName")?,
+            slug: text("slug")?,
+        })
+    }
+
+    /// Categories similar to `query`, from Twitch's search
+    fn search(query: &str) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        let req_json = serde_json::json!({
+            "query": "query($query: String!) { searchCategories(query: $query, first: 5) { edges { node { id name displayName slug } } } }",
+            "variables": {
+                "query": query
+            }
+        });
+        let response = gql(&req_json)?;
+        Ok(response
+            .get("data")
+            .ok_or("Missing data")?
+            .get("searchCategories")
+            .ok_or("Missing searchCategories")?
+            .get("edges")
+            .ok_or("Missing edges")?
+            .as_array()
+            .ok_or("Could not convert edges -> array")?
+            .iter()
+            .flat_map(|edge| Self::from_json(edge.get("node")?).ok())
+            .collect())
+    }
+
+    pub fn channels(&self, filter: StreamFilter) -> DirectoryIterator<'_> {
         DirectoryIterator {
             name: &self.name,
-            cursor: String::from(""),
+            filter,
+            cursor: Some(String::from("")),
+            seen: std::collections::HashSet::new(),
         }
     }
-    pub fn clips(&self, recency: Recency) -> DirectoryClipIterator {
+    pub fn clips(&self, recency: Recency) -> DirectoryClipIterator<'_> {
         DirectoryClipIterator {
             name: &self.name,
             recency,
             cursor: Some(String::from("")),
         }
     }
+
+    /// Past broadcasts, and optionally highlights, from every channel that streamed under the
+    /// directory, whether or not they are live
+    pub fn videos(
+        &self,
+        languages: &[String],
+        sort: VideoSort,
+        highlights: bool,
+    ) -> DirectoryVideoIterator<'_> {
+        let mut types = vec!["ARCHIVE"];
+        if highlights {
+            types.push("HIGHLIGHT");
+        }
+        DirectoryVideoIterator {
+            name: &self.name,
+            languages: languages.to_vec(),
+            sort,
+            types,
+            cursor: Some(None),
+        }
+    }
+}
+
+pub struct DirectoryVideoIterator<'a> {
+    name: &'a str,
+    languages: Vec<String>,
+    sort: VideoSort,
+    types: Vec<&'static str>,
+    /// The cursor of the next page, which is `Some(None)` for the first page
+    cursor: Option<Option<String>>,
+}
+
+impl DirectoryVideoIterator<'_> {
+    fn get_next(&mut self) -> Result<Vec<Vod>, Box<dyn std::error::Error>> {
+        let after = self.cursor.take().flatten();
+        let languages = if self.languages.is_empty() {
+            None
+        } else {
+            Some(&self.languages)
+        };
+        let req_json = serde_json::json!({
+            "query": "query($name: String!, $first: Int, $after: Cursor, $languages: [String!], $types: [BroadcastType!], $sort: VideoSort) { game(name: $name) { videos(first: $first, after: $after, languages: $languages, types: $types, sort: $sort) { edges { cursor node { id title animatedPreviewURL resourceRestriction { type } owner { login } } } pageInfo { hasNextPage } } } }",
+            "variables": {
+                "name": self.name,
+                "first": 30,
+                "after": after,
+                "languages": languages,
+                "types": self.types,
+                "sort": self.sort.as_str()
+            }
+        });
+        let response = gql(&req_json)?;
+        let game = response
+            .get("data")
+            .ok_or("Missing data")?
+            .get("game")
+            .ok_or("Missing game")?;
+        if game.is_null() {
+            return Err(format!("The directory {} does not exist", self.name).into());
+        }
+        let videos = game.get("videos").ok_or("Missing videos")?;
+        let edges = videos
+            .get("edges")
+            .ok_or("Missing edges")?
+            .as_array()
+            .ok_or("Unable to convert edges -> array")?;
+
+        let has_next_page = videos
+            .get("pageInfo")
+            .and_then(|p| p.get("hasNextPage"))
+            .and_then(|h| h.as_bool())
+            .unwrap_or(false);
+        if has_next_page {
+            self.cursor = edges
+                .last()
+                .and_then(|edge| edge.get("cursor")?.as_str())
+                .map(|cursor| Some(cursor.to_owned()));
+        }
+
+        Ok(edges
+            .par_iter()
+            .flat_map(|edge| -> Option<Vod> {
+                let node = edge.get("node")?;
+                let id = node.get("id")?.as_str()?.parse().ok()?;
+                let login = node.get("owner")?.get("login")?.as_str()?;
+                let title = format!("[{}] {}", login, node.get("title")?.as_str()?);
+                let preview_url = node
+                    .get("animatedPreviewURL")
+                    .and_then(|p| p.as_str())
+                    .unwrap_or_default();
+                let restricted = restricted(node);
+                Some(Vod {
+                    title,
+                    id,
+                    m3u8: Vod::m3u8(id, preview_url, restricted)
+                        .unwrap_or_else(|_| "Unknown link".into()),
+                    restricted,
+                    login: Some(login.to_owned()),
+                })
+            })
+            .collect())
+    }
+}
+
+impl Iterator for DirectoryVideoIterator<'_> {
+    type Item = Vec<Vod>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.as_ref()?;
+        match self.get_next() {
+            Ok(videos) => Some(videos),
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        }
+    }
 }
 
 pub struct DirectoryClipIterator<'a> {
@@ -107,12 +805,7 @@ impl Iterator for DirectoryClipIterator<'_> {
                     "filter": self.recency.as_str()
                 }
             },
-            "extensions": {
-                "persistedQuery": {
-                    "version": 1,
-                    "sha256Hash": "0d8d0eba9fc7ef77de54a7d933998e21ad7a1274c867ec565ac14ffdce77b1f9"
-                }
-            }
+            "extensions": persisted_query("ClipsCards__Game")
         }]);
         self.cursor = None;
 
@@ -131,7 +824,7 @@ impl Iterator for DirectoryClipIterator<'_> {
         self.cursor = edges
             .iter()
             .map(|edge| edge.get("cursor")?.as_str())
-            .last()
+            .next_back()
             .map(|cursor| cursor.unwrap().to_owned());
 
         Some(
@@ -164,66 +857,56 @@ impl Iterator for DirectoryClipIterator<'_> {
 
 pub struct DirectoryIterator<'a> {
     name: &'a str,
-    cursor: String,
+    filter: StreamFilter,
+    cursor: Option<String>,
+    seen: std::collections::HashSet<String>,
 }
 
-impl Iterator for DirectoryIterator<'_> {
-    type Item = Vec<Channel>;
-    fn next(&mut self) -> Option<Self::Item> {
+impl DirectoryIterator<'_> {
+    fn get_next(&mut self) -> Result<Vec<Channel>, Box<dyn std::error::Error>> {
+        let mut options = self.filter.options(&self.filter.tags);
+        // Directories leave out subscriber-only streams unless they are asked for
+        options["includeRestricted"] = serde_json::json!(["SUB_ONLY_LIVE"]);
         let req_json = serde_json::json!([{
             "operationName": "DirectoryPage_Game",
             "variables": {
                 "imageWidth": 50,
                 "name": self.name,
-                "options": {
-                    "includeRestricted": [
-                        "SUB_ONLY_LIVE"
-                    ],
-                    "sort": "RELEVANCE",
-                    "recommendationsContext": {
-                        "platform": "web"
-                    },
-                    "requestID": "JIRA-VXP-2397",
-                    "freeformTags": null,
-                    "tags":[]
-                },
-                "freeformTagsEnabled": false,
-                "sortTypeIsRecency": false,
+                "options": options,
+                "freeformTagsEnabled": !self.filter.tags.is_empty(),
+                "sortTypeIsRecency": matches!(self.filter.sort, StreamSort::Recent),
                 "limit": 30,
-                "cursor": self.cursor
+                "cursor": self.cursor.take()
             },
-            "extensions": {
-                "persistedQuery": {
-                    "version": 1,
-                    "sha256Hash": "749035333f1837aca1c5bae468a11c39604a91c9206895aa90b4657ab6213c24"
-                }
-            }
+            "extensions": persisted_query("DirectoryPage_Game")
         }]);
-        let response: serde_json::Value = gql(&req_json).unwrap();
+        let response = gql(&req_json)?;
+        let streams = response
+            .get(0)
+            .ok_or("Missing idx 0")?
+            .get("data")
+            .ok_or("Missing data")?
+            .get("game")
+            .ok_or("Missing game")?
+            .get("streams")
+            .ok_or("Missing streams")?;
 
-        let channels = response
-            .get(0)?
-            .get("data")?
-            .get("game")?
-            .get("streams")?
-            .get("edges")?
-            .as_array()?;
-        let out = channels
-            .iter()
-            .flat_map(|edge| edge.get("node"))
-            .flat_map(|node| node.get("broadcaster"))
-            .flat_map(|broadcaster| -> Option<Channel> {
-                let username = broadcaster.get("login")?.as_str()?;
-                Some(Channel {
-                    username: username.into(),
-                })
-            })
-            .collect();
-        if let Some(cursor) = channels.get(25)?.get("cursor") {
-            self.cursor = cursor.as_str()?.to_string();
-            Some(out)
-        } else {
-            None
+        let (channels, cursor) = stream_page(streams, &mut self.seen)?;
+        self.cursor = cursor;
+        Ok(channels)
+    }
+}
+
+impl Iterator for DirectoryIterator<'_> {
+    type Item = Vec<Channel>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.as_ref()?;
+        match self.get_next() {
+            Ok(channels) => Some(channels),
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
         }
     }
 }
@@ -232,23 +915,27 @@ impl Iterator for DirectoryIterator<'_> {
 pub struct Tag;
 
 impl Tag {
-    pub fn channels(tags: &[String]) -> TagIterator {
+    pub fn channels(tags: &[String], filter: StreamFilter) -> TagIterator {
+        let mut tags = tags.to_vec();
+        tags.extend(filter.tags.iter().cloned());
         TagIterator {
-            tags: tags.to_vec(),
+            tags,
+            filter,
             cursor: Some(String::from("")),
+            seen: std::collections::HashSet::new(),
         }
     }
 }
 
 pub struct TagIterator {
     tags: Vec<String>,
+    filter: StreamFilter,
     cursor: Option<String>,
+    seen: std::collections::HashSet<String>,
 }
 
-impl Iterator for TagIterator {
-    type Item = Vec<Channel>;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.cursor.as_ref()?;
+impl TagIterator {
+    fn get_next(&mut self) -> Result<Vec<Channel>, Box<dyn std::error::Error>> {
         let req_json = serde_json::json!([
         {
             "operationName": "BrowsePage_Popular",
@@ -256,49 +943,103 @@ impl Iterator for TagIterator {
                 "imageWidth": 50,
                 "limit": 30,
                 "platformType": "all",
-                "options": {
-                    "sort": "RELEVANCE",
-                    "freeformTags": self.tags,
-                    "recommendationsContext": {
-                        "platform": "web"
-                    },
-                    "requestID": "JIRA-VXP-2397"
-                },
-                "sortTypeIsRecency": false,
+                "options": self.filter.options(&self.tags),
+                "sortTypeIsRecency": matches!(self.filter.sort, StreamSort::Recent),
                 "freeformTagsEnabled": true,
-                "cursor": self.cursor,
+                "cursor": self.cursor.take(),
             },
-            "extensions": {
-                "persistedQuery": {
-                    "version": 1,
-                    "sha256Hash": "267d2d2a64e0a0d6206c039ea9948d14a9b300a927d52b2efc52d2486ff0ec65"
-                }
-            }
+            "extensions": persisted_query("BrowsePage_Popular")
         }]);
-        let request: serde_json::Value = gql(&req_json).unwrap();
-        let streamlist = request
-            .get(0)?
-            .get("data")?
-            .get("streams")?
-            .get("edges")?
-            .as_array()?;
-        let out = streamlist
-            .iter()
-            .flat_map(|e| -> Option<Channel> {
-                let username = e.get("node")?.get("broadcaster")?.get("login")?.as_str()?;
-                Some(Channel::new(username))
-            })
-            .collect();
-        self.cursor = if let Some(stream) = streamlist.get(25) {
-            if let Some(cursor) = stream.get("cursor") {
-                cursor.as_str().map(|cursor| cursor.to_owned())
-            } else {
+        let response = gql(&req_json)?;
+        let streams = response
+            .get(0)
+            .ok_or("Missing idx 0")?
+            .get("data")
+            .ok_or("Missing data")?
+            .get("streams")
+            .ok_or("Missing streams")?;
+
+        let (channels, cursor) = stream_page(streams, &mut self.seen)?;
+        self.cursor = cursor;
+        Ok(channels)
+    }
+}
+
+impl Iterator for TagIterator {
+    type Item = Vec<Channel>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.as_ref()?;
+        match self.get_next() {
+            Ok(channels) => Some(channels),
+            Err(e) => {
+                eprintln!("{}", e);
                 None
             }
-        } else {
-            None
-        };
-        Some(out)
+        }
+    }
+}
+
+/// A Twitch team, whose members are scanned like the channels of a directory
+#[derive(Debug)]
+pub struct Team {
+    name: String,
+}
+
+impl Team {
+    pub fn new<S>(name: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self { name: name.into() }
+    }
+
+    pub fn members(&self) -> Result<Vec<Channel>, Box<dyn std::error::Error>> {
+        let mut members = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let req_json = serde_json::json!({
+                "query": "query($name: String!, $after: Cursor) { team(name: $name) { members(first: 100, after: $after) { edges { cursor node { login } } pageInfo { hasNextPage } } } }",
+                "variables": {
+                    "name": self.name,
+                    "after": cursor
+                }
+            });
+            let response = gql(&req_json)?;
+            let team = response
+                .get("data")
+                .ok_or("Missing data")?
+                .get("team")
+                .ok_or("Missing team")?;
+            if team.is_null() {
+                return Err(format!("The team {} does not exist", self.name).into());
+            }
+            let page = team.get("members").ok_or("Missing members")?;
+            let edges = page
+                .get("edges")
+                .ok_or("Missing edges")?
+                .as_array()
+                .ok_or("Unable to convert edges -> array")?;
+            members.extend(
+                edges
+                    .iter()
+                    .flat_map(|edge| edge.get("node")?.get("login")?.as_str())
+                    .map(Channel::new),
+            );
+
+            let has_next_page = page
+                .get("pageInfo")
+                .and_then(|p| p.get("hasNextPage"))
+                .and_then(|h| h.as_bool())
+                .unwrap_or(false);
+            cursor = edges
+                .last()
+                .and_then(|edge| edge.get("cursor")?.as_str())
+                .map(String::from);
+            if !has_next_page || cursor.is_none() {
+                break;
+            }
+        }
+        Ok(members)
     }
 }
 
@@ -310,57 +1051,267 @@ pub struct Channel {
 impl std::str::FromStr for Channel {
     type Err = &'static str;
     fn from_str(name: &str) -> Result<Self, Self::Err> {
-        let channel = Channel::new(name);
-        if channel.exists() {
-            Ok(channel)
+        let name = name.trim();
+        if name.is_empty()
+            || name.len() > 25
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            Err("Channel names may only contain letters, numbers and underscores")
         } else {
-            Err("This channel does not exist")
+            Ok(Channel::new(name))
+        }
+    }
+}
+
+/// Public details of a channel
+#[derive(Debug)]
+pub struct ChannelInfo {
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
+    pub description: Option<String>,
+    pub followers: u64,
+    pub live: Option<String>,
+    pub last_broadcast: Option<String>,
+    pub vod_count: u64,
+}
+
+impl std::fmt::Display for ChannelInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} ({}) [{}]", self.display_name, self.login, self.id)?;
+        if let Some(description) = &self.description {
+            writeln!(f, "{}", description)?;
+        }
+        writeln!(f, "Followers: {}", self.followers)?;
+        match &self.live {
+            Some(live) => writeln!(f, "Live: {}", live)?,
+            None => writeln!(f, "Offline")?,
+        }
+        if let Some(last_broadcast) = &self.last_broadcast {
+            writeln!(f, "Last broadcast: {}", last_broadcast)?;
+        }
+        write!(f, "VODs: {}", self.vod_count)
+    }
+}
+
+impl Channel {
+    pub fn new<S>(username: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            username: username.into(),
         }
     }
-}
 
-impl Channel {
-    pub fn new<S>(username: S) -> Self
-    where
-        S: Into<String>,
-    {
-        Self {
-            username: username.into(),
+    /// Reads a list of usernames, one per line, skipping blank lines and `#` comments
+    pub fn from_file(path: &std::path::Path) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        std::fs::read_to_string(path)?
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| {
+                line.parse::<Self>()
+                    .map_err(|e| format!("{}:{}: {}: {}", path.display(), i + 1, line, e).into())
+            })
+            .collect()
+    }
+
+    pub fn clips(&self) -> self::clips::ClipIterator<'_> {
+        self::clips::ClipIterator {
+            username: &self.username,
+            cursor: Some(String::from("")),
+        }
+    }
+
+    fn exists(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        let req_json = serde_json::json!({
+            "operationName": "PlaybackAccessToken",
+            "variables": {
+                "isLive": true,
+                "login": self.username,
+                "isVod": false,
+                "vodID": "",
+                "playerType": "channel_home_carousel"
+            },
+            "extensions": persisted_query("PlaybackAccessToken")
+        });
+        let response = gql(&req_json)?;
+
+        Ok(!response
+            .get("data")
+            .ok_or("Missing data")?
+            .get("streamPlaybackAccessToken")
+            .ok_or("Missing streamPlaybackAccessToken")?
+            .is_null())
+    }
+
+    /// Logins of channels with names similar to this one
+    fn suggestions(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let req_json = serde_json::json!({
+            "query": "query($query: String!) { searchUsers(userQuery: $query, first: 5) { edges { node { login } } } }",
+            "variables": {
+                "query": self.username
+            }
+        });
+        let response = gql(&req_json)?;
+        Ok(response
+            .get("data")
+            .ok_or("Missing data")?
+            .get("searchUsers")
+            .ok_or("Missing searchUsers")?
+            .get("edges")
+            .ok_or("Missing edges")?
+            .as_array()
+            .ok_or("Could not convert edges -> array")?
+            .iter()
+            .flat_map(|edge| edge.get("node")?.get("login")?.as_str())
+            .map(String::from)
+            .collect())
+    }
+
+    /// Checks that the channel exists, suggesting similarly named channels when it does not
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.exists()? {
+            return Ok(());
+        }
+        let suggestions = self.suggestions().unwrap_or_default();
+        if suggestions.is_empty() {
+            Err(format!("The channel {} does not exist", self.username).into())
+        } else {
+            Err(format!(
+                "The channel {} does not exist, did you mean: {}?",
+                self.username,
+                suggestions.join(", ")
+            )
+            .into())
         }
     }
 
-    pub fn clips(&self) -> self::clips::ClipIterator {
-        self::clips::ClipIterator {
-            username: &self.username,
-            cursor: Some(String::from("")),
+    pub fn info(&self) -> Result<ChannelInfo, Box<dyn std::error::Error>> {
+        let req_json = serde_json::json!({
+            "query": "query($login: String!) { user(login: $login) { id login displayName description followers { totalCount } stream { viewersCount title game { displayName } } lastBroadcast { startedAt title } videos { totalCount } } }",
+            "variables": {
+                "login": self.username
+            }
+        });
+        let response = gql(&req_json)?;
+        let user = response
+            .get("data")
+            .ok_or("Missing data")?
+            .get("user")
+            .ok_or("Missing user")?;
+        if user.is_null() {
+            self.validate()?;
+            return Err("Missing user".into());
         }
+
+        let text = |value: Option<&serde_json::Value>| -> Option<String> {
+            value
+                .and_then(|v| v.as_str())
+                .filter(|v| !v.is_empty())
+                .map(String::from)
+        };
+        let live = user.get("stream").filter(|s| !s.is_null()).map(|stream| {
+            format!(
+                "{} for {} viewers ({})",
+                text(stream.get("game").and_then(|g| g.get("displayName"))).unwrap_or_default(),
+                stream
+                    .get("viewersCount")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or_default(),
+                text(stream.get("title")).unwrap_or_default()
+            )
+        });
+        let last_broadcast = user
+            .get("lastBroadcast")
+            .and_then(|b| Some((text(b.get("startedAt"))?, b)))
+            .map(|(started, broadcast)| match text(broadcast.get("title")) {
+                Some(title) => format!("{} ({})", started, title),
+                None => started,
+            });
+
+        Ok(ChannelInfo {
+            id: text(user.get("id")).ok_or("Missing id")?,
+            login: text(user.get("login")).ok_or("Missing login")?,
+            display_name: text(user.get("displayName")).ok_or("Missing displayName")?,
+            description: text(user.get("description")),
+            followers: user
+                .get("followers")
+                .and_then(|f| f.get("totalCount"))
+                .and_then(|c| c.as_u64())
+                .unwrap_or_default(),
+            live,
+            last_broadcast,
+            vod_count: user
+                .get("videos")
+                .and_then(|v| v.get("totalCount"))
+                .and_then(|c| c.as_u64())
+                .unwrap_or_default(),
+        })
     }
 
-    fn exists(&self) -> bool {
+    /// Image links of the global chat badges and those of this channel, such as its subscriber
+    /// badges, by badge name and version
+    pub fn badge_images(
+        &self,
+    ) -> Result<std::collections::HashMap<(String, String), String>, Box<dyn std::error::Error>>
+    {
         let req_json = serde_json::json!({
-            "operationName": "PlaybackAccessToken",
+            "query": "query($login: String!) { badges { setID version image1x } user(login: $login) { broadcastBadges { setID version image1x } } }",
             "variables": {
-                "isLive": true,
-                "login": self.username,
-                "isVod": false,
-                "vodID": "",
-                "playerType": "channel_home_carousel"
-            },
-            "extensions": {
-                "persistedQuery": {
-                    "version": 1,
-                    "sha256Hash": "0828119ded1c13477966434e15800ff57ddacf13ba1911c129dc2200705b0712"
-                }
+                "login": self.username
             }
         });
-        let response: serde_json::Value = gql(&req_json).unwrap();
+        let response = gql(&req_json)?;
+        let data = response.get("data").ok_or("Missing data")?;
+
+        let global = data.get("badges").and_then(|b| b.as_array());
+        let channel = data
+            .get("user")
+            .and_then(|u| u.get("broadcastBadges"))
+            .and_then(|b| b.as_array());
+        // Channel badges come last so they replace the global badges of the same name
+        Ok(global
+            .into_iter()
+            .chain(channel)
+            .flatten()
+            .filter_map(|badge| {
+                Some((
+                    (
+                        badge.get("setID")?.as_str()?.to_owned(),
+                        badge.get("version")?.as_str()?.to_owned(),
+                    ),
+                    badge.get("image1x")?.as_str()?.to_owned(),
+                ))
+            })
+            .collect())
+    }
 
-        return !response
+    /// The ID of the VOD being recorded from the channel's current stream, if it is live
+    pub fn live_vod(&self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let req_json = serde_json::json!({
+            "query": "query($login: String!) { user(login: $login) { stream { archiveVideo { id } } } }",
+            "variables": {
+                "login": self.username
+            }
+        });
+        let response = gql(&req_json)?;
+        let user = response
             .get("data")
-            .unwrap()
-            .get("streamPlaybackAccessToken")
-            .unwrap()
-            .is_null();
+            .ok_or("Missing data")?
+            .get("user")
+            .ok_or("Missing user")?;
+        if user.is_null() {
+            return Err(format!("{} does not exist", self.username).into());
+        }
+        Ok(user
+            .get("stream")
+            .and_then(|s| s.get("archiveVideo"))
+            .and_then(|v| v.get("id"))
+            .and_then(|id| id.as_str())
+            .map(String::from))
     }
 
     pub fn videos(&self) -> Result<Vec<Vod>, Box<dyn std::error::Error>> {
@@ -374,12 +1325,7 @@ impl Channel {
                                                  "videoSort": "TIME",
                                                  "cursor": ""
                                              },
-                                             "extensions": {
-                                                 "persistedQuery": {
-                                                     "version": 1,
-                                                     "sha256Hash": "a937f1d22e269e39a03b509f65a7490f9fc247d7f83d6ac1421523e3b68042cb"
-                                                 }
-                                             }
+                                             "extensions": persisted_query("FilterableVideoTower_Videos")
                                          }
         ]);
 
@@ -404,11 +1350,14 @@ impl Channel {
                 let vod = v.get("node").unwrap();
                 let title = vod.get("title")?.as_str()?.to_string();
                 let id = vod.get("id").unwrap().as_str()?.parse().unwrap();
-                let m3u8 = Vod::m3u8(id, vod.get("animatedPreviewURL")?.as_str()?);
+                let restricted = restricted(vod);
+                let m3u8 = Vod::m3u8(id, vod.get("animatedPreviewURL")?.as_str()?, restricted);
                 Some(Vod {
                     title,
                     id,
                     m3u8: m3u8.unwrap_or("Unknown link".into()),
+                    restricted,
+                    login: Some(self.username.to_owned()),
                 })
             })
             .collect();
@@ -428,12 +1377,84 @@ pub mod clips {
         pub url: String,
     }
 
+    /// The VOD a clip was cut from, along with where in it the clip starts
+    pub struct ClipSource {
+        pub vod_id: u32,
+        pub offset: f64,
+        pub duration: f64,
+    }
+
     impl std::fmt::Display for Clip {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             write!(f, "[{}][{}] {}", self.username, self.title, self.slug)
         }
     }
 
+    impl Clip {
+        /// Resolves the VOD and offset of the clip, returning None if the VOD is no longer available
+        pub fn source(&self) -> Result<Option<ClipSource>, Box<dyn std::error::Error>> {
+            let req_json = serde_json::json!({
+                "query": "query($slug: ID!) { clip(slug: $slug) { durationSeconds videoOffsetSeconds video { id } } }",
+                "variables": {
+                    "slug": self.slug
+                }
+            });
+
+            let response = super::gql(&req_json)?;
+            let clip = response
+                .get("data")
+                .ok_or("Missing data")?
+                .get("clip")
+                .ok_or("Missing clip")?;
+            let video = clip.get("video").ok_or("Missing video")?;
+            if video.is_null() {
+                return Ok(None);
+            }
+
+            let vod_id = video
+                .get("id")
+                .ok_or("Missing id")?
+                .as_str()
+                .ok_or("Could not convert id -> str")?
+                .parse()?;
+            let offset = clip
+                .get("videoOffsetSeconds")
+                .ok_or("Missing videoOffsetSeconds")?
+                .as_f64()
+                .ok_or("Could not convert videoOffsetSeconds -> f64")?;
+            let duration = clip
+                .get("durationSeconds")
+                .ok_or("Missing durationSeconds")?
+                .as_f64()
+                .ok_or("Could not convert durationSeconds -> f64")?;
+
+            Ok(Some(ClipSource {
+                vod_id,
+                offset,
+                duration,
+            }))
+        }
+
+        /// Retrieves the VOD chat from `before` seconds prior to the clip until the end of the clip
+        pub fn context(
+            &self,
+            before: f64,
+        ) -> Result<Vec<crate::common::Message>, Box<dyn std::error::Error>> {
+            let source = match self.source()? {
+                Some(source) => source,
+                None => return Ok(Vec::new()),
+            };
+            let start = (source.offset - before).max(0.0);
+            let end = source.offset + source.duration;
+
+            Ok(super::chat::ChatIterator::from_offset(source.vod_id, start)
+                .flatten()
+                .skip_while(|m| m.timestamp.unwrap_or_default() < start)
+                .take_while(|m| m.timestamp.unwrap_or_default() <= end)
+                .collect())
+        }
+    }
+
     impl ClipIterator<'_> {
         fn get_next(&mut self) -> Result<Vec<Clip>, Box<dyn std::error::Error>> {
             let req_json = serde_json::json!([{
@@ -446,12 +1467,7 @@ pub mod clips {
                     },
                     "cursor": self.cursor,
                 },
-                "extensions": {
-                    "persistedQuery": {
-                        "version": 1,
-                        "sha256Hash": "b73ad2bfaecfd30a9e6c28fada15bd97032c83ec77a0440766a56fe0bd632777"
-                    }
-                }
+                "extensions": super::persisted_query("ClipsCards__User")
             }]);
 
             let response: serde_json::Value = super::gql(&req_json).unwrap();
@@ -519,6 +1535,17 @@ pub struct Vod {
     title: String,
     id: u32,
     m3u8: String,
+    restricted: bool,
+    /// Login of the channel that broadcast the VOD, when it is already known
+    login: Option<String>,
+}
+
+/// Whether a video node is restricted to subscribers
+fn restricted(node: &serde_json::Value) -> bool {
+    node.get("resourceRestriction")
+        .and_then(|r| r.get("type"))
+        .and_then(|t| t.as_str())
+        .is_some()
 }
 
 impl Vod {
@@ -527,14 +1554,158 @@ impl Vod {
             title: String::new(),
             id,
             m3u8: String::new(),
+            restricted: false,
+            login: None,
+        }
+    }
+
+    /// Looks up the title and playlist of a single VOD
+    pub fn fetch(id: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        let req_json = serde_json::json!({
+            "query": "query($id: ID!) { video(id: $id) { title animatedPreviewURL resourceRestriction { type } owner { login } } }",
+            "variables": {
+                "id": id.to_string()
+            }
+        });
+        let response = gql(&req_json)?;
+        let video = response
+            .get("data")
+            .ok_or("Missing data")?
+            .get("video")
+            .ok_or("Missing video")?;
+        if video.is_null() {
+            return Err("This video ID may not exist".into());
+        }
+
+        let title = video
+            .get("title")
+            .and_then(|t| t.as_str())
+            .unwrap_or_default()
+            .to_owned();
+        let preview_url = video
+            .get("animatedPreviewURL")
+            .and_then(|p| p.as_str())
+            .unwrap_or_default();
+        let restricted = restricted(video);
+        let m3u8 = Vod::m3u8(id, preview_url, restricted)?;
+        let login = video
+            .get("owner")
+            .and_then(|o| o.get("login"))
+            .and_then(|l| l.as_str())
+            .map(String::from);
+        Ok(Self {
+            title,
+            id,
+            m3u8,
+            restricted,
+            login,
+        })
+    }
+
+    /// The channel that broadcast the VOD
+    pub fn owner(&self) -> Result<Channel, Box<dyn std::error::Error>> {
+        if let Some(login) = &self.login {
+            return Ok(Channel::new(login));
+        }
+        let req_json = serde_json::json!({
+            "query": "query($id: ID!) { video(id: $id) { owner { login } } }",
+            "variables": {
+                "id": self.id.to_string()
+            }
+        });
+        let response = gql(&req_json)?;
+        let login = response
+            .get("data")
+            .ok_or("Missing data")?
+            .get("video")
+            .ok_or("Missing video")?
+            .get("owner")
+            .ok_or("Missing owner; This video ID may not exist")?
+            .get("login")
+            .ok_or("Missing login")?
+            .as_str()
+            .ok_or("Could not convert login -> string")?;
+        Ok(Channel::new(login))
+    }
+
+    /// The index playlist of the VOD, if it could be found
+    pub fn playlist(&self) -> Option<&str> {
+        Some(self.m3u8.as_str())
+            .filter(|m3u8| reqwest::Url::parse(m3u8).is_ok_and(|url| url.path().ends_with(".m3u8")))
+    }
+
+    /// The playlist of a subscriber-only VOD, signed for the account of the configured OAuth token
+    fn restricted_m3u8(id: u32) -> Result<String, Box<dyn std::error::Error>> {
+        let auth_required = || AuthRequired {
+            what: format!("VOD {}", id),
+        };
+        if OAUTH_TOKEN.is_none() {
+            return Err(Box::new(auth_required()));
+        }
+
+        let req_json = serde_json::json!({
+            "operationName": "PlaybackAccessToken",
+            "variables": {
+                "isLive": false,
+                "login": "",
+                "isVod": true,
+                "vodID": id.to_string(),
+                "playerType": "site"
+            },
+            "extensions": persisted_query("PlaybackAccessToken")
+        });
+        let response = gql(&req_json)?;
+        let token = response
+            .get("data")
+            .ok_or("Missing data")?
+            .get("videoPlaybackAccessToken")
+            .ok_or("Missing videoPlaybackAccessToken")?;
+        let value = token.get("value").and_then(|v| v.as_str());
+        let signature = token.get("signature").and_then(|s| s.as_str());
+        let (value, signature) = value.zip(signature).ok_or_else(auth_required)?;
+
+        // Twitch still signs a token for accounts without access, but marks it as such
+        let access: serde_json::Value = serde_json::from_str(value)?;
+        if access
+            .get("chansub")
+            .and_then(|c| c.get("restricted_bitrates"))
+            .and_then(|r| r.as_array())
+            .is_some_and(|r| !r.is_empty())
+        {
+            return Err(Box::new(auth_required()));
         }
+
+        let playlist = reqwest::Url::parse_with_params(
+            &format!("https://usher.ttvnw.net/vod/{}.m3u8", id),
+            &[
+                ("sig", signature),
+                ("token", value),
+                ("allow_source", "true"),
+            ],
+        )?;
+        Ok(playlist.to_string())
     }
 
-    fn m3u8(id: u32, preview_url: &str) -> Result<String, Box<dyn std::error::Error>> {
+    fn m3u8(
+        id: u32,
+        preview_url: &str,
+        restricted: bool,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if restricted {
+            return Vod::restricted_m3u8(id);
+        }
         if preview_url.is_empty() {
             return Ok(format!("https://twitch.tv/videos/{}", id));
         }
 
+        if let Some(domain) = preview_url
+            .strip_prefix("https://")
+            .and_then(|url| url.split('/').next())
+            .and_then(|host| host.strip_suffix(".cloudfront.net"))
+        {
+            crate::twitchrecover::learn_domain(domain);
+        }
+
         let chunked_index = preview_url
             .find("storyboards")
             .ok_or("Could not find storboards")?;
@@ -545,12 +1716,7 @@ impl Vod {
                 "channelLogin": "",
                 "videoID": format!(r#"{}"#, id),
             },
-            "extensions": {
-                "persistedQuery": {
-                    "version": 1,
-                    "sha256Hash": "226edb3e692509f727fd56821f5653c05740242c82b0388883e0c0e75dcbf687"
-                }
-            }
+            "extensions": persisted_query("VideoMetadata")
         }]);
 
         let response = gql(&req_json)?;
@@ -571,26 +1737,320 @@ impl Vod {
             _ => format!("https://twitch.tv/videos/{}", id),
         })
     }
+
+    /// The chat of the VOD, annotated with `chapters` as already fetched by the caller
+    pub fn comments_with_chapters(
+        &self,
+        chapters: Vec<Chapter>,
+    ) -> Box<dyn crate::common::ChatIterator> {
+        Box::new(chat::ChatIterator::new(self.id).with_chapters(chapters))
+    }
 }
 
 impl crate::common::Vod for Vod {
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    /// The login of the broadcaster, or the VOD ID when it isn't known
+    fn channel(&self) -> String {
+        self.login.clone().unwrap_or_else(|| self.id.to_string())
+    }
+
     fn comments(&self) -> Box<dyn crate::common::ChatIterator> {
         Box::new(chat::ChatIterator::new(self.id))
     }
+
+    fn link(&self, offset: f64) -> String {
+        let offset = offset.max(0.0) as u64;
+        format!(
+            "https://www.twitch.tv/videos/{}?t={}h{}m{}s",
+            self.id,
+            offset / 3600,
+            offset / 60 % 60,
+            offset % 60
+        )
+    }
 }
 
 impl std::fmt::Display for Vod {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.restricted {
+            write!(f, "[sub-only] ")?;
+        }
         write!(f, "{} {}\n{}", self.title, self.id, self.m3u8)
     }
 }
 
+/// A section of a VOD, marking where the streamer changed game or category
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub start: f64,
+    pub duration: f64,
+    pub description: String,
+}
+
+impl Chapter {
+    /// Retrieves the chapters of a VOD. VODs without chapter markers are given a single chapter
+    /// for the game they were streamed under.
+    pub fn fetch(id: u32) -> Result<Vec<Chapter>, Box<dyn std::error::Error>> {
+        let req_json = serde_json::json!({
+            "query": "query($id: ID!) { video(id: $id) { lengthSeconds game { displayName } moments(momentRequestType: VIDEO_CHAPTER_MARKERS) { edges { node { description positionMilliseconds durationMilliseconds } } } } }",
+            "variables": {
+                "id": id.to_string()
+            }
+        });
+        let response = gql(&req_json)?;
+        let video = response
+            .get("data")
+            .ok_or("Missing data")?
+            .get("video")
+            .ok_or("Missing video")?;
+        if video.is_null() {
+            return Err("This video ID may not exist".into());
+        }
+
+        let chapters: Vec<Chapter> = video
+            .get("moments")
+            .and_then(|m| m.get("edges"))
+            .and_then(|e| e.as_array())
+            .map(|edges| {
+                edges
+                    .iter()
+                    .flat_map(|edge| -> Option<Chapter> {
+                        let node = edge.get("node")?;
+                        Some(Chapter {
+                            start: node.get("positionMilliseconds")?.as_f64()? / 1000.0,
+                            duration: node.get("durationMilliseconds")?.as_f64()? / 1000.0,
+                            description: node.get("description")?.as_str()?.to_owned(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !chapters.is_empty() {
+            return Ok(chapters);
+        }
+
+        Ok(video
+            .get("game")
+            .and_then(|g| g.get("displayName"))
+            .and_then(|g| g.as_str())
+            .map(|game| Chapter {
+                start: 0.0,
+                duration: video
+                    .get("lengthSeconds")
+                    .and_then(|l| l.as_f64())
+                    .unwrap_or_default(),
+                description: game.to_owned(),
+            })
+            .into_iter()
+            .collect())
+    }
+
+    /// The chapter that was active at `offset` seconds into the VOD
+    pub fn at(chapters: &[Chapter], offset: f64) -> Option<&Chapter> {
+        chapters
+            .iter()
+            .rev()
+            .find(|chapter| chapter.start <= offset)
+    }
+}
+
 mod chat {
-    use colored::Colorize;
+    /// Reads a page of comments, along with the replies and user notices that the persisted
+    /// `VideoCommentsByOffsetOrCursor` query leaves out. The cheermotes of the channel are only
+    /// selected with the first page.
+    const COMMENTS_QUERY: &str = "query($videoID: ID, $contentOffsetSeconds: Int, $cursor: Cursor, $cheermotes: Boolean!) { cheerConfig @include(if: $cheermotes) { groups { nodes { prefix } } } video(id: $videoID) { id owner @include(if: $cheermotes) { cheer { cheerGroups { nodes { prefix } } } } comments(contentOffsetSeconds: $contentOffsetSeconds, after: $cursor) { edges { cursor node { id contentOffsetSeconds commenter { login } message { fragments { text emote { emoteID } } userBadges { setID version } userColor reply { parentMessageID parentUserLogin parentMessageBody } userNoticeParams } } } pageInfo { hasNextPage } } } }";
+
+    /// The prefixes of the cheermotes usable in the channel of a VOD, both global and the
+    /// channel's own, in lowercase, from the `data` of the first page of comments
+    pub(super) fn cheermotes(
+        data: &serde_json::Value,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let global = data
+            .get("cheerConfig")
+            .ok_or("Missing cheerConfig")?
+            .get("groups")
+            .ok_or("Missing groups")?;
+        let channel = data
+            .get("video")
+            .and_then(|v| v.get("owner"))
+            .and_then(|o| o.get("cheer"))
+            .and_then(|c| c.get("cheerGroups"));
+        Ok([Some(global), channel]
+            .into_iter()
+            .flatten()
+            .flat_map(|groups| groups.as_array().into_iter().flatten())
+            .flat_map(|group| {
+                group
+                    .get("nodes")
+                    .and_then(|n| n.as_array())
+                    .into_iter()
+                    .flatten()
+            })
+            .filter_map(|node| Some(node.get("prefix")?.as_str()?.to_lowercase()))
+            .collect())
+    }
+
+    /// The bits cheered in a message, summed over the words that are one of `cheermotes`
+    /// followed by an amount, such as "Cheer100"
+    pub(super) fn bits(body: &str, cheermotes: &[String]) -> u64 {
+        body.split_whitespace()
+            .filter_map(|word| {
+                let prefix = word.trim_end_matches(|c: char| c.is_ascii_digit());
+                let amount = word[prefix.len()..].parse::<u64>().ok()?;
+                cheermotes
+                    .contains(&prefix.to_lowercase())
+                    .then_some(amount)
+            })
+            .sum()
+    }
+
+    /// Joins the fragments of a comment into its body, recording where each Twitch emote is
+    fn fragments(fragments: &[serde_json::Value]) -> (String, Vec<crate::common::Emote>) {
+        let mut body = String::new();
+        let mut emotes = Vec::new();
+        let mut length = 0;
+        for fragment in fragments {
+            let text = fragment
+                .get("text")
+                .and_then(|t| t.as_str())
+                .unwrap_or_default();
+            let count = text.chars().count();
+            if let Some(id) = fragment
+                .get("emote")
+                .and_then(|e| e.get("emoteID"))
+                .and_then(|id| id.as_str())
+                .filter(|_| count > 0)
+            {
+                emotes.push(crate::common::Emote {
+                    id: id.to_owned(),
+                    start: length,
+                    end: length + count - 1,
+                });
+            }
+            body.push_str(text);
+            length += count;
+        }
+        (body, emotes)
+    }
+
+    fn badges(message: &serde_json::Value) -> Vec<crate::common::Badge> {
+        message
+            .get("userBadges")
+            .and_then(|b| b.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|badge| {
+                Some(crate::common::Badge {
+                    name: badge
+                        .get("setID")?
+                        .as_str()
+                        .filter(|s| !s.is_empty())?
+                        .to_owned(),
+                    version: badge.get("version")?.as_str()?.to_owned(),
+                })
+            })
+            .collect()
+    }
+
+    fn reply(message: &serde_json::Value) -> Option<crate::common::Reply> {
+        let reply = message.get("reply").filter(|r| !r.is_null())?;
+        let text = |key: &str| reply.get(key).and_then(|v| v.as_str()).map(String::from);
+        Some(crate::common::Reply {
+            id: text("parentMessageID"),
+            user: text("parentUserLogin"),
+            body: text("parentMessageBody"),
+        })
+    }
+
+    /// The subscription, gift or raid a comment announces, from the same `msg-id` and
+    /// `msg-param-*` parameters that IRC sends
+    fn notice(message: &serde_json::Value) -> Option<crate::common::Event> {
+        let params = message.get("userNoticeParams")?.as_object()?;
+        let kind = params.get("msg-id")?.as_str()?;
+        Some(crate::common::Event::from_notice(
+            kind,
+            |key| match params.get(key)? {
+                serde_json::Value::String(value) => Some(value.to_owned()),
+                value => Some(value.to_string()),
+            },
+        ))
+    }
+
+    /// The messages in a page of `comments`, and the cursor of the next page if there is one
+    pub(super) fn page(
+        comments: &serde_json::Value,
+        chapters: &[super::Chapter],
+        cheermotes: &[String],
+    ) -> Result<(Vec<crate::common::Message>, Option<String>), Box<dyn std::error::Error>> {
+        let edges = comments
+            .get("edges")
+            .ok_or("Missing edges")?
+            .as_array()
+            .ok_or("Unable to convert comments -> array")?;
+
+        let cursor = if comments
+            .get("pageInfo")
+            .ok_or("Missing pageInfo")?
+            .get("hasNextPage")
+            .ok_or("Missing hasNextPage")?
+            .as_bool()
+            .ok_or("Could not convert hasNextPage -> bool")?
+        {
+            edges.last().and_then(|c| {
+                c.get("cursor")
+                    .and_then(|c| c.as_str().map(|s| s.to_owned()))
+            })
+        } else {
+            None
+        };
+
+        let messages = edges
+            .iter()
+            .flat_map(|e| e.get("node"))
+            .filter_map(|comment| -> Option<crate::common::Message> {
+                let user = Some(comment.get("commenter")?.get("login")?.as_str()?.to_owned());
+                let message = comment.get("message")?;
+                let (body, emotes) = fragments(message.get("fragments")?.as_array()?);
+
+                let color = message
+                    .get("userColor")?
+                    .as_str()
+                    .and_then(crate::common::parse_color);
+                let timestamp = comment.get("contentOffsetSeconds")?.as_f64()?;
+
+                Some(crate::common::Message {
+                    user,
+                    bits: bits(&body, cheermotes),
+                    body,
+                    timestamp: Some(timestamp),
+                    id: comment
+                        .get("id")
+                        .and_then(|id| id.as_str())
+                        .map(String::from),
+                    color,
+                    badges: badges(message),
+                    emotes,
+                    event: notice(message),
+                    chapter: super::Chapter::at(chapters, timestamp)
+                        .map(|chapter| chapter.description.to_owned()),
+                    reply: reply(message),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        Ok((messages, cursor))
+    }
 
     pub struct ChatIterator {
         pub id: String,
         cursor: Option<String>,
+        offset: Option<f64>,
+        chapters: Option<Vec<super::Chapter>>,
+        cheermotes: Option<Vec<String>>,
+        error: Option<String>,
     }
 
     impl ChatIterator {
@@ -598,97 +2058,93 @@ mod chat {
             Self {
                 id: id.to_string(),
                 cursor: Some(String::from("")),
+                offset: None,
+                chapters: None,
+                cheermotes: None,
+                error: None,
+            }
+        }
+
+        /// Annotates the chat with chapters that have already been fetched, rather than fetching
+        /// them with the first page
+        pub fn with_chapters(self, chapters: Vec<super::Chapter>) -> Self {
+            Self {
+                chapters: Some(chapters),
+                ..self
+            }
+        }
+
+        /// Starts reading chat from `offset` seconds into the VOD, rather than from the beginning
+        pub fn from_offset(id: u32, offset: f64) -> Self {
+            Self {
+                offset: Some(offset),
+                ..Self::new(id)
             }
         }
 
         fn get_next(&mut self) -> Result<Vec<crate::common::Message>, Box<dyn std::error::Error>> {
-            let req_json = serde_json::json!([{
-                "operationName": "VideoCommentsByOffsetOrCursor",
-                "variables": {
+            let first = self.cheermotes.is_none();
+            let variables = match self.offset.take() {
+                Some(offset) => serde_json::json!({
                     "videoID": self.id,
-                    "cursor": self.cursor.as_ref().ok_or("No cursor")?
-                },
-                "extensions": {
-                    "persistedQuery": {
-                        "version":1,
-                        "sha256Hash": "b70a3591ff0f4e0313d126c6a1502d79a1c02baebb288227c582044aa76adf6a"
-                    }
-                }
-            }]);
-            let comment_json = super::gql(&req_json)?;
-            let comments = comment_json
-                .get(0)
-                .ok_or("Missing idx 0")?
-                .get("data")
-                .ok_or("Missing data")?
-                .get("video")
-                .ok_or("Missing video")?
+                    "contentOffsetSeconds": offset as u64,
+                    "cheermotes": first
+                }),
+                None => serde_json::json!({
+                    "videoID": self.id,
+                    "cursor": self.cursor.as_deref().filter(|c| !c.is_empty()),
+                    "cheermotes": first
+                }),
+            };
+            let req_json = serde_json::json!({
+                "query": COMMENTS_QUERY,
+                "variables": variables
+            });
+            let response = super::gql(&req_json)?;
+            let data = response.get("data").ok_or("Missing data")?;
+            let video = data.get("video").ok_or("Missing video")?;
+            let comments = video
                 .get("comments")
                 .ok_or("Missing comments; This video ID may not exist")?;
-            let edges = comments
-                .get("edges")
-                .ok_or("Missing edges")?
-                .as_array()
-                .ok_or("Unable to convert comments -> array")?;
+            // The comments of a subscriber-only VOD are null for accounts without access
+            if comments.is_null() && !video.is_null() {
+                return Err(Box::new(super::AuthRequired {
+                    what: format!("The chat of VOD {}", self.id),
+                }));
+            }
 
-            if comments
-                .get("pageInfo")
-                .ok_or("Missing pageInfo")?
-                .get("hasNextPage")
-                .ok_or("Missing hasNextPage")?
-                .as_bool()
-                .ok_or("Could not convert hasNextPage -> bool")?
-            {
-                self.cursor = edges.last().and_then(|c| {
-                    c.get("cursor")
-                        .and_then(|c| c.as_str().map(|s| s.to_owned()))
+            if first {
+                let cheermotes = cheermotes(data).unwrap_or_else(|e| {
+                    eprintln!(
+                        "Unable to load the cheermotes of VOD {}, so bits aren't counted: {}",
+                        self.id, e
+                    );
+                    Vec::new()
                 });
-            } else {
-                self.cursor = None;
+                self.cheermotes = Some(cheermotes);
+            }
+            if self.chapters.is_none() {
+                let chapters = match self.id.parse() {
+                    Ok(id) => super::Chapter::fetch(id).unwrap_or_default(),
+                    Err(_) => Vec::new(),
+                };
+                self.chapters = Some(chapters);
             }
 
-            Ok(edges
-                .iter()
-                .flat_map(|e| e.get("node"))
-                .filter_map(|comment| -> Option<crate::common::Message> {
-                    let mut user: Option<String> =
-                        Some(comment.get("commenter")?.get("login")?.as_str()?.to_owned());
-                    let message = comment.get("message")?;
-                    let body = message
-                        .get("fragments")?
-                        .get(0)?
-                        .get("text")?
-                        .as_str()?
-                        .to_owned();
-
-                    let colorcode = message.get("userColor")?.as_str();
-                    let color = match colorcode {
-                        Some(code) => {
-                            let code = code.trim_start_matches('#');
-                            Some(colored::Color::TrueColor {
-                                r: u8::from_str_radix(&code[0..2], 16).unwrap(),
-                                g: u8::from_str_radix(&code[2..4], 16).unwrap(),
-                                b: u8::from_str_radix(&code[4..6], 16).unwrap(),
-                            })
-                        }
-                        None => None,
-                    };
-
-                    if let Some(color) = color {
-                        user = Some(user.unwrap().color(color).to_string());
-                    }
-                    let timestamp = comment.get("contentOffsetSeconds")?.as_f64()?;
-
-                    Some(crate::common::Message {
-                        user,
-                        body,
-                        timestamp: Some(timestamp),
-                    })
-                })
-                .collect())
+            let (messages, cursor) = page(
+                comments,
+                self.chapters.as_deref().unwrap_or_default(),
+                self.cheermotes.as_deref().unwrap_or_default(),
+            )?;
+            self.cursor = cursor;
+            Ok(messages)
+        }
+    }
+    impl crate::common::ChatIterator for ChatIterator {
+        fn error(&self) -> Option<&str> {
+            self.error.as_deref()
         }
     }
-    impl crate::common::ChatIterator for ChatIterator {}
     impl Iterator for ChatIterator {
         type Item = Vec<crate::common::Message>;
         fn next(&mut self) -> Option<Self::Item> {
@@ -697,6 +2153,8 @@ mod chat {
                     Ok(messages) => Some(messages),
                     Err(e) => {
                         eprintln!("{}", e);
+                        self.error = Some(e.to_string());
+                        self.cursor = None;
                         None
                     }
                 }
@@ -706,3 +2164,119 @@ mod chat {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs_are_ascii() {
+        assert_eq!(slugify("Just Chatting"), "just-chatting");
+        assert_eq!(slugify("Pokémon Legends: Arceus"), "pokemon-legends-arceus");
+        assert_eq!(
+            slugify("Tom Clancy's Rainbow Six Siege"),
+            "tom-clancys-rainbow-six-siege"
+        );
+        assert_eq!(slugify("Counter-Strike 2"), "counter-strike-2");
+        assert_eq!(slugify("原神 Genshin"), "genshin");
+    }
+
+    #[test]
+    fn resolves_near_misses() {
+        let slugs = [
+            "just-chatting",
+            "just-dance",
+            "minecraft",
+            "minecraft-dungeons",
+        ];
+        assert_eq!(closest("Just Chating", &slugs), Some("just-chatting"));
+        assert_eq!(closest("minecraf", &slugs), Some("minecraft"));
+        assert_eq!(closest("dungeons", &slugs), Some("minecraft-dungeons"));
+        assert_eq!(closest("just", &slugs), None);
+        assert_eq!(closest("fortnite", &slugs), None);
+    }
+
+    #[test]
+    fn playlists_may_have_a_query() {
+        let mut vod = Vod::new(1);
+        vod.m3u8 = "https://usher.ttvnw.net/vod/1.m3u8?allow_source=true&sig=abc".to_owned();
+        assert_eq!(vod.playlist(), Some(vod.m3u8.as_str()));
+        vod.m3u8 = "https://d2nvs31859zcd8.cloudfront.net/abc/chunked/index-dvr.m3u8".to_owned();
+        assert!(vod.playlist().is_some());
+        vod.m3u8 =
+            "https://vod-secure.twitch.tv/_404/404_processing_%{width}x%{height}.png".to_owned();
+        assert_eq!(vod.playlist(), None);
+        vod.m3u8 = String::new();
+        assert_eq!(vod.playlist(), None);
+    }
+
+    #[test]
+    fn parses_comments() {
+        let response: serde_json::Value =
+            serde_json::from_str(include_str!("../tests/fixtures/comments.json")).unwrap();
+        let data = &response["data"];
+        let cheermotes = chat::cheermotes(data).unwrap();
+        assert_eq!(cheermotes, ["cheer", "party", "twitchcheer"]);
+
+        let chapters = [Chapter {
+            start: 0.0,
+            duration: 3600.0,
+            description: String::from("Just Chatting"),
+        }];
+        let (messages, cursor) =
+            chat::page(&data["video"]["comments"], &chapters, &cheermotes).unwrap();
+        assert_eq!(cursor.as_deref(), Some("eyJpZCI6ImE1In0"));
+        assert_eq!(messages.len(), 5);
+
+        let first = &messages[0];
+        assert_eq!(first.user.as_deref(), Some("viewer_one"));
+        assert_eq!(first.body, "hello Kappa cheer100 party100");
+        assert_eq!(first.timestamp, Some(12.0));
+        assert_eq!(first.bits, 200);
+        assert_eq!(first.emotes.len(), 1);
+        assert_eq!((first.emotes[0].start, first.emotes[0].end), (6, 10));
+        assert_eq!(first.badges.len(), 1);
+        assert_eq!(first.chapter.as_deref(), Some("Just Chatting"));
+        assert!(first.reply.is_none() && first.event.is_none());
+
+        let reply = messages[1].reply.as_ref().unwrap();
+        assert_eq!(reply.id.as_deref(), Some("a1"));
+        assert_eq!(reply.user.as_deref(), Some("viewer_one"));
+        assert!(messages[1].is_kind(&[String::from("reply")]));
+
+        let kinds: Vec<&str> = messages[2..]
+            .iter()
+            .map(|m| m.event.as_ref().unwrap().kind())
+            .collect();
+        assert_eq!(kinds, ["giftsub", "giftsub", "raid"]);
+        assert!(matches!(
+            messages[2].event,
+            Some(crate::common::Event::GiftSub { count: Some(5), .. })
+        ));
+        assert!(matches!(
+            &messages[3].event,
+            Some(crate::common::Event::GiftSub { recipient: Some(r), .. }) if r == "Lucky"
+        ));
+        assert!(matches!(
+            messages[4].event,
+            Some(crate::common::Event::Raid { viewers: Some(42) })
+        ));
+    }
+
+    #[test]
+    fn last_page_has_no_cursor() {
+        let comments = serde_json::json!({ "edges": [], "pageInfo": { "hasNextPage": false } });
+        let (messages, cursor) = chat::page(&comments, &[], &[]).unwrap();
+        assert!(messages.is_empty() && cursor.is_none());
+        assert!(chat::cheermotes(&serde_json::json!({ "cheerConfig": null })).is_err());
+    }
+
+    #[test]
+    fn counts_only_real_cheermotes() {
+        let cheermotes = vec!["cheer".to_owned(), "party".to_owned()];
+        assert_eq!(chat::bits("Cheer100 hype cheer50", &cheermotes), 150);
+        assert_eq!(chat::bits("party100 party", &cheermotes), 100);
+        assert_eq!(chat::bits("kappa100 2024 cheer", &cheermotes), 0);
+        assert_eq!(chat::bits("Cheer100", &[]), 0);
+    }
+}
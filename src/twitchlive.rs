@@ -0,0 +1,552 @@
+use std::io::{BufRead, Write};
+
+pub const IRC_SERVER: &str = "irc.chat.twitch.tv:6667";
+
+/// A single line received from the IRC server, split into its IRCv3 tags, prefix, command and
+/// parameters
+#[derive(Debug)]
+struct IrcLine<'a> {
+    tags: std::collections::HashMap<&'a str, String>,
+    prefix: Option<&'a str>,
+    command: &'a str,
+    params: Vec<&'a str>,
+    trailing: Option<&'a str>,
+}
+
+fn unescape_tag(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('s') => out.push(' '),
+                Some(':') => out.push(';'),
+                Some('r') => out.push('\r'),
+                Some('n') => out.push('\n'),
+                Some(other) => out.push(other),
+                None => {}
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+impl<'a> IrcLine<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(&['\r', '\n'][..]);
+
+        let mut tags = std::collections::HashMap::new();
+        if let Some(tagged) = rest.strip_prefix('@') {
+            let (raw_tags, remaining) = tagged.split_once(' ')?;
+            for tag in raw_tags.split(';') {
+                let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+                tags.insert(key, unescape_tag(value));
+            }
+            rest = remaining;
+        }
+
+        let mut prefix = None;
+        if let Some(prefixed) = rest.strip_prefix(':') {
+            let (raw_prefix, remaining) = prefixed.split_once(' ')?;
+            prefix = Some(raw_prefix);
+            rest = remaining;
+        }
+
+        let (rest, trailing) = match rest.split_once(" :") {
+            Some((rest, trailing)) => (rest, Some(trailing)),
+            None => (rest, None),
+        };
+        let mut params = rest.split(' ').filter(|p| !p.is_empty());
+        let command = params.next()?;
+
+        Some(Self {
+            tags,
+            prefix,
+            command,
+            params: params.collect(),
+            trailing,
+        })
+    }
+
+    fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .get(key)
+            .map(|value| value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn channel(&self) -> Option<String> {
+        self.params
+            .first()
+            .map(|channel| channel.trim_start_matches('#').to_owned())
+    }
+
+    fn nick(&self) -> Option<&str> {
+        self.prefix?.split('!').next()
+    }
+
+    fn badges(&self) -> Vec<crate::common::Badge> {
+        self.tag("badges")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|badge| {
+                let (name, version) = badge.split_once('/')?;
+                Some(crate::common::Badge {
+                    name: name.to_owned(),
+                    version: version.to_owned(),
+                })
+            })
+            .collect()
+    }
+
+    fn emotes(&self) -> Vec<crate::common::Emote> {
+        let mut emotes: Vec<crate::common::Emote> = self
+            .tag("emotes")
+            .unwrap_or_default()
+            .split('/')
+            .filter_map(|emote| emote.split_once(':'))
+            .flat_map(|(id, ranges)| {
+                ranges.split(',').filter_map(move |range| {
+                    let (start, end) = range.split_once('-')?;
+                    Some(crate::common::Emote {
+                        id: id.to_owned(),
+                        start: start.parse().ok()?,
+                        end: end.parse().ok()?,
+                    })
+                })
+            })
+            .collect();
+        emotes.sort_by_key(|emote| emote.start);
+        emotes
+    }
+
+    fn reply(&self) -> Option<crate::common::Reply> {
+        let id = self.tag("reply-parent-msg-id")?;
+        Some(crate::common::Reply {
+            id: Some(id.to_owned()),
+            user: self
+                .tag("reply-parent-display-name")
+                .or_else(|| self.tag("reply-parent-user-login"))
+                .map(String::from),
+            body: self.tag("reply-parent-msg-body").map(String::from),
+        })
+    }
+
+    /// When the server received the message, from the `tmi-sent-ts` tag, falling back to when it
+    /// arrived here
+    fn sent(&self) -> f64 {
+        match self
+            .tag("tmi-sent-ts")
+            .and_then(|ts| ts.parse::<f64>().ok())
+        {
+            Some(millis) => millis / 1000.0,
+            None => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        }
+    }
+
+    fn to_message(&self) -> Option<crate::common::Message> {
+        let channel = self.channel();
+        let sent = Some(self.sent());
+        match self.command {
+            "PRIVMSG" => {
+                let body = self.trailing?;
+                let body = match body
+                    .strip_prefix("\u{1}ACTION ")
+                    .and_then(|action| action.strip_suffix('\u{1}'))
+                {
+                    Some(action) => action,
+                    None => body,
+                };
+                Some(crate::common::Message {
+                    user: self
+                        .tag("display-name")
+                        .or_else(|| self.nick())
+                        .map(String::from),
+                    body: body.to_owned(),
+                    sent,
+                    id: self.tag("id").map(String::from),
+                    channel,
+                    color: self.tag("color").and_then(crate::common::parse_color),
+                    badges: self.badges(),
+                    emotes: self.emotes(),
+                    reply: self.reply(),
+                    bits: self
+                        .tag("bits")
+                        .and_then(|b| b.parse().ok())
+                        .unwrap_or_default(),
+                    ..Default::default()
+                })
+            }
+            "USERNOTICE" => Some(crate::common::Message {
+                user: self
+                    .tag("display-name")
+                    .or_else(|| self.tag("login"))
+                    .map(String::from),
+                body: self
+                    .trailing
+                    .or_else(|| self.tag("system-msg"))
+                    .unwrap_or_default()
+                    .to_owned(),
+                sent,
+                id: self.tag("id").map(String::from),
+                channel,
+                color: self.tag("color").and_then(crate::common::parse_color),
+                badges: self.badges(),
+                emotes: self.emotes(),
+                event: Some(crate::common::Event::from_notice(
+                    self.tag("msg-id")?,
+                    |key| self.tag(key).map(String::from),
+                )),
+                ..Default::default()
+            }),
+            "CLEARMSG" => Some(crate::common::Message {
+                user: self.tag("login").map(String::from),
+                body: self.trailing.unwrap_or_default().to_owned(),
+                sent,
+                channel,
+                event: Some(crate::common::Event::Deleted {
+                    target: self.tag("target-msg-id")?.to_owned(),
+                }),
+                ..Default::default()
+            }),
+            "CLEARCHAT" => Some(crate::common::Message {
+                sent,
+                channel,
+                event: Some(crate::common::Event::Cleared {
+                    user: self.trailing.map(String::from),
+                    duration: self.tag("ban-duration").and_then(|d| d.parse().ok()),
+                }),
+                ..Default::default()
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The first delay before reconnecting, which doubles after each failed attempt up to the maximum
+const MIN_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+const MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(60);
+/// Twitch sends a PING about every five minutes, so a connection that stays silent for longer is
+/// treated as dropped
+const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(6 * 60);
+
+struct Connection {
+    reader: std::io::BufReader<std::net::TcpStream>,
+    writer: std::net::TcpStream,
+}
+
+impl Connection {
+    fn open(
+        server: &str,
+        channels: &[String],
+        timeout: std::time::Duration,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let stream = std::net::TcpStream::connect(server)?;
+        stream.set_read_timeout(Some(timeout))?;
+        let mut writer = stream.try_clone()?;
+
+        write!(
+            writer,
+            "CAP REQ :twitch.tv/tags twitch.tv/commands\r\nPASS SCHMOOPIIE\r\nNICK justinfan{}\r\n",
+            std::process::id() % 100000
+        )?;
+        for channel in channels {
+            write!(writer, "JOIN #{}\r\n", channel.to_lowercase())?;
+        }
+        writer.flush()?;
+
+        Ok(Self {
+            reader: std::io::BufReader::new(stream),
+            writer,
+        })
+    }
+
+    /// Reads until at least one message arrives, then takes any others that have already been
+    /// received, along with whether the connection is still open
+    fn read(&mut self) -> Result<(Vec<crate::common::Message>, bool), Box<dyn std::error::Error>> {
+        let mut messages = Vec::new();
+        let mut line = String::new();
+        while messages.is_empty() || !self.reader.buffer().is_empty() {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return Ok((messages, false)),
+                Ok(_) => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err("Nothing received from the server for too long".into())
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            let irc = match IrcLine::parse(&line) {
+                Some(irc) => irc,
+                None => continue,
+            };
+            match irc.command {
+                "PING" => {
+                    write!(
+                        self.writer,
+                        "PONG :{}\r\n",
+                        irc.trailing.unwrap_or_default()
+                    )?;
+                    self.writer.flush()?;
+                }
+                "RECONNECT" => return Ok((messages, false)),
+                _ => messages.extend(irc.to_message()),
+            }
+        }
+        Ok((messages, true))
+    }
+}
+
+/// Anonymous connection to Twitch chat, yielding messages from the joined channels as they
+/// arrive. The connection is reopened, with backoff, whenever the server drops it or asks for a
+/// reconnect, or stops sending anything at all.
+pub struct LiveChat {
+    server: String,
+    channels: Vec<String>,
+    connection: Option<Connection>,
+    backoff: std::time::Duration,
+    timeout: std::time::Duration,
+}
+
+impl LiveChat {
+    pub fn connect(server: &str, channels: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            server: server.to_owned(),
+            channels: channels.to_vec(),
+            connection: Some(Connection::open(server, channels, READ_TIMEOUT)?),
+            backoff: MIN_BACKOFF,
+            timeout: READ_TIMEOUT,
+        })
+    }
+
+    fn reconnect(&mut self) -> Connection {
+        loop {
+            std::thread::sleep(self.backoff);
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+            match Connection::open(&self.server, &self.channels, self.timeout) {
+                Ok(connection) => {
+                    eprintln!("Reconnected to {}", self.server);
+                    return connection;
+                }
+                Err(e) => eprintln!("Unable to reconnect to {}: {}", self.server, e),
+            }
+        }
+    }
+}
+
+impl Iterator for LiveChat {
+    type Item = Vec<crate::common::Message>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut connection = match self.connection.take() {
+                Some(connection) => connection,
+                None => self.reconnect(),
+            };
+            match connection.read() {
+                Ok((messages, open)) => {
+                    if open {
+                        self.connection = Some(connection);
+                    } else {
+                        eprintln!("Disconnected from {}, reconnecting", self.server);
+                    }
+                    if !messages.is_empty() {
+                        self.backoff = MIN_BACKOFF;
+                        return Some(messages);
+                    }
+                }
+                Err(e) => eprintln!("{}, reconnecting", e),
+            }
+        }
+    }
+}
+
+impl crate::common::ChatIterator for LiveChat {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tagged_privmsg() {
+        let irc = IrcLine::parse(
+            "@badges=moderator/1,subscriber/12;color=#FF4500;display-name=Viewer;\
+             emotes=25:0-4,12-16/1902:6-10;id=abc-123;tmi-sent-ts=1700000000123 \
+             :viewer!viewer@viewer.tmi.twitch.tv PRIVMSG #channel :Kappa Keepo Kappa\r\n",
+        )
+        .unwrap();
+        assert_eq!(irc.command, "PRIVMSG");
+        assert_eq!(irc.nick(), Some("viewer"));
+
+        let message = irc.to_message().unwrap();
+        assert_eq!(message.user.as_deref(), Some("Viewer"));
+        assert_eq!(message.channel.as_deref(), Some("channel"));
+        assert_eq!(message.body, "Kappa Keepo Kappa");
+        assert_eq!(message.id.as_deref(), Some("abc-123"));
+        assert_eq!(message.color, Some((255, 69, 0)));
+        assert_eq!(message.sent, Some(1700000000.123));
+        assert_eq!(message.timestamp, None);
+        let badges: Vec<&str> = message.badges.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(badges, ["moderator", "subscriber"]);
+        let emotes: Vec<(&str, usize)> = message
+            .emotes
+            .iter()
+            .map(|e| (e.id.as_str(), e.start))
+            .collect();
+        assert_eq!(emotes, [("25", 0), ("1902", 6), ("25", 12)]);
+    }
+
+    #[test]
+    fn unescapes_tags() {
+        let irc = IrcLine::parse(
+            "@system-msg=5\\sraiders\\sfrom\\sOther\\:\\shi;msg-id=raid;msg-param-viewerCount=5;\
+             login=other :tmi.twitch.tv USERNOTICE #channel\r\n",
+        )
+        .unwrap();
+        let message = irc.to_message().unwrap();
+        assert_eq!(message.body, "5 raiders from Other; hi");
+        assert!(matches!(
+            message.event,
+            Some(crate::common::Event::Raid { viewers: Some(5) })
+        ));
+    }
+
+    #[test]
+    fn parses_actions_and_replies() {
+        let irc = IrcLine::parse(
+            "@reply-parent-msg-id=parent;reply-parent-display-name=Other;\
+             reply-parent-msg-body=hello\\sthere :viewer!viewer@viewer.tmi.twitch.tv \
+             PRIVMSG #channel :\u{1}ACTION waves\u{1}",
+        )
+        .unwrap();
+        let message = irc.to_message().unwrap();
+        assert_eq!(message.user.as_deref(), Some("viewer"));
+        assert_eq!(message.body, "waves");
+        let reply = message.reply.unwrap();
+        assert_eq!(reply.user.as_deref(), Some("Other"));
+        assert_eq!(reply.body.as_deref(), Some("hello there"));
+    }
+
+    #[test]
+    fn parses_moderation() {
+        let cleared = IrcLine::parse("@ban-duration=600 :tmi.twitch.tv CLEARCHAT #channel :troll")
+            .unwrap()
+            .to_message()
+            .unwrap();
+        assert!(matches!(
+            cleared.event,
+            Some(crate::common::Event::Cleared { user: Some(ref user), duration: Some(600) })
+                if user == "troll"
+        ));
+
+        let deleted =
+            IrcLine::parse("@login=troll;target-msg-id=abc :tmi.twitch.tv CLEARMSG #channel :bad")
+                .unwrap()
+                .to_message()
+                .unwrap();
+        assert!(matches!(
+            deleted.event,
+            Some(crate::common::Event::Deleted { ref target }) if target == "abc"
+        ));
+    }
+
+    #[test]
+    fn ignores_other_commands() {
+        let ping = IrcLine::parse("PING :tmi.twitch.tv").unwrap();
+        assert_eq!(ping.command, "PING");
+        assert_eq!(ping.trailing, Some("tmi.twitch.tv"));
+        assert!(ping.to_message().is_none());
+        assert!(
+            IrcLine::parse(":tmi.twitch.tv 001 justinfan1 :Welcome, GLHF!")
+                .unwrap()
+                .to_message()
+                .is_none()
+        );
+        assert!(IrcLine::parse("").is_none());
+    }
+
+    #[test]
+    fn reconnects_to_a_local_server() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let lines = [
+                "PING :tmi.twitch.tv\r\n\
+                 @tmi-sent-ts=1000 :a!a@a.tmi.twitch.tv PRIVMSG #channel :first\r\n\
+                 @tmi-sent-ts=2000 :b!b@b.tmi.twitch.tv PRIVMSG #channel :second\r\n\
+                 :tmi.twitch.tv RECONNECT\r\n",
+                "@tmi-sent-ts=3000 :c!c@c.tmi.twitch.tv PRIVMSG #channel :third\r\n",
+            ];
+            for lines in lines {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while !line.starts_with("JOIN") {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                }
+                assert_eq!(line, "JOIN #channel\r\n");
+                stream.write_all(lines.as_bytes()).unwrap();
+                if lines.starts_with("PING") {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    assert_eq!(line, "PONG :tmi.twitch.tv\r\n");
+                }
+            }
+        });
+
+        let messages: Vec<crate::common::Message> =
+            LiveChat::connect(&server, &[String::from("Channel")])
+                .unwrap()
+                .flatten()
+                .take(3)
+                .collect();
+        let bodies: Vec<&str> = messages.iter().map(|m| m.body.as_str()).collect();
+        assert_eq!(bodies, ["first", "second", "third"]);
+        let sent: Vec<Option<f64>> = messages.iter().map(|m| m.sent).collect();
+        assert_eq!(sent, [Some(1.0), Some(2.0), Some(3.0)]);
+    }
+
+    #[test]
+    fn reconnects_when_the_server_goes_quiet() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let mut quiet = Vec::new();
+            for body in ["first", "second"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                write!(
+                    stream,
+                    ":a!a@a.tmi.twitch.tv PRIVMSG #channel :{}\r\n",
+                    body
+                )
+                .unwrap();
+                // Keep the connection open without sending anything else
+                quiet.push(stream);
+            }
+            std::thread::sleep(std::time::Duration::from_secs(5));
+        });
+
+        let timeout = std::time::Duration::from_millis(200);
+        let channels = [String::from("channel")];
+        let chat = LiveChat {
+            server: server.clone(),
+            channels: channels.to_vec(),
+            connection: Some(Connection::open(&server, &channels, timeout).unwrap()),
+            backoff: std::time::Duration::from_millis(10),
+            timeout,
+        };
+        let bodies: Vec<String> = chat.flatten().take(2).map(|m| m.body).collect();
+        assert_eq!(bodies, ["first", "second"]);
+    }
+}
@@ -13,89 +13,564 @@ const CLOUDFRONT_DOMAINS: [&str; 9] = [
     "d1ymi26ma8va5x",
 ];
 
-#[derive(Debug)]
+/// The CloudFront domains to search: the built in ones, followed by those from the
+/// `recovery.domains` list in the config and those learned from the preview images of live VODs
+struct Domains {
+    all: Vec<String>,
+    /// Only the learned domains are saved, so that a domain removed from the config is no longer
+    /// searched
+    learned: Vec<String>,
+    path: Option<std::path::PathBuf>,
+}
+
+impl Domains {
+    fn load(configured: &[serde_json::Value], path: Option<std::path::PathBuf>) -> Self {
+        let learned: Vec<String> = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str::<Vec<serde_json::Value>>(&text).ok())
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|d| d.as_str())
+            .map(String::from)
+            .collect();
+        let mut all: Vec<String> = CLOUDFRONT_DOMAINS.iter().map(|d| d.to_string()).collect();
+        for domain in configured
+            .iter()
+            .flat_map(|d| d.as_str())
+            .chain(learned.iter().map(String::as_str))
+        {
+            if !all.iter().any(|known| known == domain) {
+                all.push(domain.to_owned());
+            }
+        }
+        Self { all, learned, path }
+    }
+
+    /// Adds a domain that isn't known yet and saves the learned domains, returning whether it
+    /// was new
+    fn learn(&mut self, domain: &str) -> Result<bool, String> {
+        if self.all.iter().any(|known| known == domain) {
+            return Ok(false);
+        }
+        self.all.push(domain.to_owned());
+        self.learned.push(domain.to_owned());
+
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| String::from("Unable to find a data directory"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(path, serde_json::json!(self.learned).to_string())
+            .map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref DOMAINS: std::sync::Mutex<Domains> = {
+        let configured = crate::config::CONFIG
+            .get("recovery")
+            .and_then(|r| r.get("domains"))
+            .and_then(|d| d.as_array())
+            .cloned()
+            .unwrap_or_default();
+        std::sync::Mutex::new(Domains::load(&configured, learned_path()))
+    };
+}
+
+fn learned_path() -> Option<std::path::PathBuf> {
+    Some(crate::config::data_dir()?.join("cloudfront_domains.json"))
+}
+
+/// The CloudFront domains to search when recovering a VOD
+pub fn domains() -> Vec<String> {
+    DOMAINS.lock().unwrap().all.clone()
+}
+
+/// Records a CloudFront domain that Twitch is currently serving VODs from, saving it so that
+/// recovery can use it in the future
+pub fn learn_domain(domain: &str) {
+    if let Err(e) = DOMAINS.lock().unwrap().learn(domain) {
+        eprintln!("Unable to save CloudFront domain {}: {}", domain, e);
+    }
+}
+
 pub struct Channel {
-    value: u64,
+    name: String,
+    sources: Vec<Box<dyn history::StreamHistorySource>>,
 }
 
+/// A VOD recovered from the CloudFront storage of a stream
 #[derive(Debug)]
 pub struct Video {
-    id: u64,
-    title: Option<String>,
-    link: String,
+    pub id: u64,
+    pub channel: String,
+    pub start: i64,
+    pub title: Option<String>,
+    pub link: String,
+    pub domain: String,
+    pub variants: Vec<String>,
 }
 
 impl Channel {
-    pub fn new(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let json: serde_json::Value = crate::common::CLIENT
-            .get(format!(
-                "https://sullygnome.com/api/standardsearch/{}",
-                name
-            ))
-            .header(reqwest::header::USER_AGENT, crate::common::USER_AGENT)
-            .send()?
-            .json()?;
-
-        let top_value = json
-            .get(0)
-            .ok_or("Missing idx 0, No results found")?
-            .get("value")
-            .ok_or("Missing value")?
-            .as_u64()
-            .ok_or("Could not convert value -> u64")?;
+    /// Uses sullygnome as the only stream history source
+    pub fn new(name: &str) -> Self {
+        Self::with_sources(name, vec![Box::new(history::SullyGnome::default())])
+    }
 
-        Ok(Self { value: top_value })
+    pub fn with_sources(name: &str, sources: Vec<Box<dyn history::StreamHistorySource>>) -> Self {
+        Self {
+            name: name.to_owned(),
+            sources,
+        }
     }
 
     pub fn unix_time(time: &str) -> Result<i64, chrono::ParseError> {
         Ok(chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%SZ")?.timestamp())
     }
 
-    pub fn videos(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let json: serde_json::Value = crate::common::CLIENT
-            .get(&format!(
-                "https://sullygnome.com/api/tables/channeltables/streams/365/{}/%20/1/1/desc/0/100",
-                self.value
-            ))
-            .header(reqwest::header::USER_AGENT, crate::common::USER_AGENT)
-            .send()?
-            .json()?;
-        let data = json
-            .get("data")
-            .ok_or("Missing data")?
-            .as_array()
-            .ok_or("Could not convert data -> array")?;
-        let sequencer = oqueue::Sequencer::stdout();
-        rayon::scope_fifo(|t| {
-            for video in data {
-                t.spawn_fifo(|_| {
-                    let task = sequencer.begin();
-                    let rvideo = |video: &serde_json::Value| -> Option<Video> {
-                        let stream_id = video.get("streamId")?.as_u64()?;
-                        let start_timestamp = video.get("startDateTime")?.as_str()?;
-                        let unix_timestamp = Self::unix_time(start_timestamp).unwrap();
-                        let channel_name = video.get("channelurl")?.as_str()?;
-                        let starttime = video
-                            .get("starttime")
-                            .map(|s| s.as_str().unwrap().to_owned());
-                        Video::new(stream_id, unix_timestamp, channel_name, starttime)
-                    };
-                    if let Some(video) = rvideo(video) {
-                        writeln!(task, "{}\n", video);
+    /// Merges the streams from every source, newest first. When sources disagree, the earlier
+    /// source is kept, with any missing title filled in from the later ones.
+    pub fn streams(&self) -> Result<Vec<history::StreamRecord>, Box<dyn std::error::Error>> {
+        let mut streams: Vec<history::StreamRecord> = Vec::new();
+        let mut indices: std::collections::HashMap<u64, usize> = std::collections::HashMap::new();
+        let mut failures = Vec::new();
+
+        for source in &self.sources {
+            match source.streams(&self.name) {
+                Ok(records) => {
+                    for record in records {
+                        match indices.get(&record.stream_id) {
+                            Some(&i) => {
+                                let existing = &mut streams[i];
+                                if existing.title.is_none() {
+                                    existing.title = record.title;
+                                }
+                            }
+                            None => {
+                                indices.insert(record.stream_id, streams.len());
+                                streams.push(record);
+                            }
+                        }
+                    }
+                }
+                Err(e) => failures.push(format!("{}: {}", source.name(), e)),
+            }
+        }
+
+        if !failures.is_empty() && failures.len() == self.sources.len() {
+            return Err(failures.join("\n").into());
+        }
+        failures.iter().for_each(|failure| eprintln!("{}", failure));
+
+        streams.sort_by_key(|stream| std::cmp::Reverse(stream.start));
+        Ok(streams)
+    }
+
+    pub fn videos(&self) -> Result<Vec<Video>, Box<dyn std::error::Error>> {
+        Ok(self
+            .streams()?
+            .par_iter()
+            .filter_map(|stream| {
+                Video::new(
+                    stream.stream_id,
+                    stream.start,
+                    &stream.channel,
+                    stream.title.clone(),
+                )
+                .unwrap_or_else(|e| {
+                    eprintln!("Unable to check stream {}: {}", stream.stream_id, e);
+                    None
+                })
+            })
+            .collect())
+    }
+}
+
+/// The outcome of recovering one row of a batch
+pub struct BatchRow {
+    pub line: usize,
+    pub username: String,
+    pub stream_id: String,
+    pub result: Result<Option<(Video, i64)>, String>,
+}
+
+fn recover_row(
+    username: &str,
+    stream_id: &str,
+    start: &str,
+    window: u32,
+) -> Result<Option<(Video, i64)>, String> {
+    let id = stream_id
+        .parse()
+        .map_err(|e| format!("Invalid stream ID: {}", e))?;
+    let timestamp = history::parse_time(start).ok_or("Invalid start time")?;
+    Video::search_window(id, timestamp, username, None, window)
+}
+
+/// The `username,stream_id,start_time` fields of every row of a batch, along with its line
+/// number. Blank lines and lines starting with `#` are skipped, as is a header on the first row.
+fn batch_rows(input: &str) -> Vec<(usize, Result<[&str; 3], String>)> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .enumerate()
+        .filter_map(|(row, (line, text))| {
+            let fields: Vec<&str> = text.split(',').map(|field| field.trim()).collect();
+            match fields[..] {
+                [username, stream_id, start, ..] => {
+                    if row == 0
+                        && username.eq_ignore_ascii_case("username")
+                        && stream_id.parse::<u64>().is_err()
+                    {
+                        return None;
                     }
-                });
+                    Some((line, Ok([username, stream_id, start])))
+                }
+                _ => Some((
+                    line,
+                    Err(String::from("Expected username,stream_id,start_time")),
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Recovers every `username,stream_id,start_time` row of `input` in parallel, with all of the
+/// rows sharing a budget of `concurrency` requests in flight. A header row is skipped, as are
+/// blank lines and lines starting with `#`.
+pub fn recover_batch(
+    input: &str,
+    window: u32,
+    concurrency: usize,
+) -> Result<Vec<BatchRow>, Box<dyn std::error::Error>> {
+    let rows = batch_rows(input);
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(concurrency)
+        .build()?;
+    Ok(pool.install(|| {
+        rows.par_iter()
+            .map(|(line, fields)| match fields {
+                Ok([username, stream_id, start]) => BatchRow {
+                    line: *line,
+                    username: username.to_string(),
+                    stream_id: stream_id.to_string(),
+                    result: recover_row(username, stream_id, start, window),
+                },
+                Err(e) => BatchRow {
+                    line: *line,
+                    username: String::new(),
+                    stream_id: String::new(),
+                    result: Err(e.clone()),
+                },
+            })
+            .collect()
+    }))
+}
+
+pub mod history {
+    /// A stream that a channel has broadcast, as reported by a stream history source
+    #[derive(Debug, Clone)]
+    pub struct StreamRecord {
+        pub stream_id: u64,
+        pub start: i64,
+        pub channel: String,
+        pub title: Option<String>,
+    }
+
+    pub trait StreamHistorySource: Send + Sync {
+        fn name(&self) -> &str;
+        fn streams(&self, channel: &str) -> Result<Vec<StreamRecord>, Box<dyn std::error::Error>>;
+    }
+
+    /// Creates the source named by `spec`, which is either the name of a tracker site or the path
+    /// of a local file. `sullygnome:DAYS` goes back `DAYS` days instead of the default 365.
+    pub fn from_spec(spec: &str) -> Box<dyn StreamHistorySource> {
+        match spec {
+            "sullygnome" => Box::new(SullyGnome::default()),
+            "twitchtracker" => Box::new(TwitchTracker),
+            _ => match spec
+                .strip_prefix("sullygnome:")
+                .and_then(|days| days.parse().ok())
+            {
+                Some(days) => Box::new(SullyGnome { days }),
+                None => Box::new(LocalFile::new(spec)),
+            },
+        }
+    }
+
+    const PAGE_SIZE: u64 = 100;
+
+    /// Streams from sullygnome, going back `days` days
+    pub struct SullyGnome {
+        pub days: u32,
+    }
+
+    impl Default for SullyGnome {
+        fn default() -> Self {
+            Self { days: 365 }
+        }
+    }
+
+    impl SullyGnome {
+        fn channel_id(name: &str) -> Result<u64, Box<dyn std::error::Error>> {
+            let json: serde_json::Value = crate::common::CLIENT
+                .get(format!(
+                    "https://sullygnome.com/api/standardsearch/{}",
+                    name
+                ))
+                .header(reqwest::header::USER_AGENT, crate::common::USER_AGENT)
+                .send()?
+                .json()?;
+
+            Ok(json
+                .get(0)
+                .ok_or("Missing idx 0, No results found")?
+                .get("value")
+                .ok_or("Missing value")?
+                .as_u64()
+                .ok_or("Could not convert value -> u64")?)
+        }
+    }
+
+    impl StreamHistorySource for SullyGnome {
+        fn name(&self) -> &str {
+            "sullygnome"
+        }
+
+        fn streams(&self, channel: &str) -> Result<Vec<StreamRecord>, Box<dyn std::error::Error>> {
+            let id = Self::channel_id(channel)?;
+            let mut streams = Vec::new();
+            let mut start = 0;
+            loop {
+                let json: serde_json::Value = crate::common::CLIENT
+                    .get(format!(
+                        "https://sullygnome.com/api/tables/channeltables/streams/{}/{}/%20/1/1/desc/{}/{}",
+                        self.days, id, start, PAGE_SIZE
+                    ))
+                    .header(reqwest::header::USER_AGENT, crate::common::USER_AGENT)
+                    .send()?
+                    .json()?;
+                let data = json
+                    .get("data")
+                    .ok_or("Missing data")?
+                    .as_array()
+                    .ok_or("Could not convert data -> array")?;
+
+                streams.extend(data.iter().filter_map(|video| -> Option<StreamRecord> {
+                    let start_timestamp = video.get("startDateTime")?.as_str()?;
+                    Some(StreamRecord {
+                        stream_id: video.get("streamId")?.as_u64()?,
+                        start: super::Channel::unix_time(start_timestamp).ok()?,
+                        channel: video.get("channelurl")?.as_str()?.to_owned(),
+                        title: video
+                            .get("starttime")
+                            .and_then(|s| s.as_str())
+                            .map(|s| s.to_owned()),
+                    })
+                }));
+
+                start += PAGE_SIZE;
+                let total = json
+                    .get("recordsTotal")
+                    .and_then(|t| t.as_u64())
+                    .unwrap_or_default();
+                if data.is_empty() || start >= total {
+                    break;
+                }
+            }
+            Ok(streams)
+        }
+    }
+
+    lazy_static::lazy_static! {
+        static ref TWITCHTRACKER_ROW_MATCHER: regex::Regex = regex::Regex::new(r#"data-order="(\d{4}-\d\d-\d\d \d\d:\d\d:\d\d)"[^>]*>\s*<a href="/[^/"]+/streams/(\d+)""#).unwrap();
+    }
+
+    /// Streams listed on the twitchtracker stream history page of a channel. Only the first page
+    /// is read, which holds the most recent streams, so older streams need sullygnome or a local
+    /// file.
+    pub struct TwitchTracker;
+
+    impl StreamHistorySource for TwitchTracker {
+        fn name(&self) -> &str {
+            "twitchtracker"
+        }
+
+        fn streams(&self, channel: &str) -> Result<Vec<StreamRecord>, Box<dyn std::error::Error>> {
+            let channel = channel.to_lowercase();
+            let page = crate::common::CLIENT
+                .get(format!("https://twitchtracker.com/{}/streams", channel))
+                .header(reqwest::header::USER_AGENT, crate::common::USER_AGENT)
+                .send()?
+                .error_for_status()?
+                .text()?;
+
+            Ok(TWITCHTRACKER_ROW_MATCHER
+                .captures_iter(&page)
+                .filter_map(|row| -> Option<StreamRecord> {
+                    let start = chrono::NaiveDateTime::parse_from_str(&row[1], "%Y-%m-%d %H:%M:%S")
+                        .ok()?
+                        .timestamp();
+                    Some(StreamRecord {
+                        stream_id: row[2].parse().ok()?,
+                        start,
+                        channel: channel.to_owned(),
+                        title: None,
+                    })
+                })
+                .collect())
+        }
+    }
+
+    /// Streams read from a local file, either a JSON array of objects with `stream_id`, `start`
+    /// and `title` keys, or CSV rows of `stream_id,start[,title]`. Start times can be unix
+    /// timestamps or in the form `2022-01-01T00:00:00Z`.
+    pub struct LocalFile {
+        path: std::path::PathBuf,
+    }
+
+    impl LocalFile {
+        pub fn new<P>(path: P) -> Self
+        where
+            P: Into<std::path::PathBuf>,
+        {
+            Self { path: path.into() }
+        }
+    }
+
+    pub fn parse_time(time: &str) -> Option<i64> {
+        let time = time.trim();
+        time.parse()
+            .ok()
+            .or_else(|| super::Channel::unix_time(time).ok())
+    }
+
+    impl StreamHistorySource for LocalFile {
+        fn name(&self) -> &str {
+            self.path.to_str().unwrap_or("file")
+        }
+
+        fn streams(&self, channel: &str) -> Result<Vec<StreamRecord>, Box<dyn std::error::Error>> {
+            let text = std::fs::read_to_string(&self.path)?;
+            let channel = channel.to_lowercase();
+
+            if self.path.extension().is_some_and(|e| e == "json") {
+                let json: serde_json::Value = serde_json::from_str(&text)?;
+                return Ok(json
+                    .as_array()
+                    .ok_or("Could not convert streams -> array")?
+                    .iter()
+                    .filter_map(|stream| -> Option<StreamRecord> {
+                        let start = match stream.get("start")? {
+                            serde_json::Value::String(start) => parse_time(start)?,
+                            start => start.as_i64()?,
+                        };
+                        Some(StreamRecord {
+                            stream_id: stream.get("stream_id")?.as_u64()?,
+                            start,
+                            channel: channel.to_owned(),
+                            title: stream
+                                .get("title")
+                                .and_then(|t| t.as_str())
+                                .map(String::from),
+                        })
+                    })
+                    .collect());
+            }
+
+            Ok(text
+                .lines()
+                .filter_map(|line| -> Option<StreamRecord> {
+                    let mut fields = line.splitn(3, ',');
+                    Some(StreamRecord {
+                        stream_id: fields.next()?.trim().parse().ok()?,
+                        start: parse_time(fields.next()?)?,
+                        channel: channel.to_owned(),
+                        title: fields.next().map(|t| t.trim().to_owned()),
+                    })
+                })
+                .collect())
+        }
+    }
+}
+
+fn playlist_link(domain: &str, channel_name: &str, stream_id: u64, timestamp: i64) -> String {
+    let body = format!("{}_{}_{}", channel_name, stream_id, timestamp);
+    let hash = format!("{:x}", sha1::Sha1::digest(&body));
+    format!(
+        "https://{}.cloudfront.net/{}_{}/chunked/index-dvr.m3u8",
+        domain,
+        &hash[0..20],
+        body
+    )
+}
+
+/// Whether a playlist exists, as opposed to the request failing
+fn exists(link: &str) -> Result<bool, String> {
+    match crate::common::CLIENT.get(link).send() {
+        Ok(response) => Ok(response.status().is_success()),
+        Err(e) => Err(format!("{}: {}", link, e)),
+    }
+}
+
+/// Runs `check` over every candidate in order and returns the first that exists. Candidates
+/// whose requests failed are not treated as missing: when nothing is found and any request
+/// failed, the search is an error rather than a miss.
+fn first_existing<T, F>(candidates: &[T], check: F) -> Result<Option<&T>, String>
+where
+    T: Sync,
+    F: Fn(&T) -> Result<bool, String> + Sync,
+{
+    let errors = std::sync::Mutex::new(Vec::new());
+    let hit = candidates
+        .par_iter()
+        .find_first(|candidate| match check(candidate) {
+            Ok(found) => found,
+            Err(e) => {
+                errors.lock().unwrap().push(e);
+                false
             }
         });
-        Ok(())
+    let errors = errors.into_inner().unwrap();
+    match hit {
+        Some(hit) => Ok(Some(hit)),
+        None if errors.is_empty() => Ok(None),
+        None => Err(format!(
+            "{} of {} requests failed, first: {}",
+            errors.len(),
+            candidates.len(),
+            errors[0]
+        )),
     }
 }
 
+/// Every offset within `window` seconds of a start time on every domain, closest offset first
+/// and earlier before later, so that the closest start time wins
+fn window_candidates(window: u32, domains: &[String]) -> Vec<(i64, &str)> {
+    let window = window as i64;
+    std::iter::once(0)
+        .chain((1..=window).flat_map(|offset| [-offset, offset]))
+        .flat_map(|offset| domains.iter().map(move |domain| (offset, domain.as_str())))
+        .collect()
+}
+
 impl std::fmt::Display for Video {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "[{}]", self.id)?;
         if let Some(title) = &self.title {
             write!(f, " [{}]", title)?;
         }
+        if let Some(start) = chrono::NaiveDateTime::from_timestamp_opt(self.start, 0) {
+            write!(f, " [{}]", start.format("%Y-%m-%dT%H:%M:%SZ"))?;
+        }
+        write!(f, " [{}] [{}]", self.domain, self.variants.join(", "))?;
         write!(f, "\n{}", self.link)
     }
 }
@@ -106,35 +581,380 @@ impl Video {
         timestamp: i64,
         channel_name: &str,
         title: Option<String>,
-    ) -> Option<Self> {
-        let body = format!("{}_{}_{}", channel_name, stream_id, timestamp);
-        let hash = format!("{:x}", sha1::Sha1::digest(&body));
-        let subdirectory = format!("{}_{}", &hash[0..20], body);
+    ) -> Result<Option<Self>, String> {
+        let domains = domains();
+        let domain = first_existing(&domains, |domain| {
+            exists(&playlist_link(domain, channel_name, stream_id, timestamp))
+        })?;
 
-        let cloudfront_link = CLOUDFRONT_DOMAINS
-            .par_iter()
-            .flat_map(|domain| {
-                let link = format!(
-                    "https://{}.cloudfront.net/{}/chunked/index-dvr.m3u8",
-                    domain, &subdirectory
-                );
-                let request = crate::common::CLIENT.get(&link).send();
-                if let Ok(message) = request {
-                    if message.status().is_success() {
-                        Some(link)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-            .find_any(|_| true);
+        Ok(domain.map(|domain| {
+            let link = playlist_link(domain, channel_name, stream_id, timestamp);
+            Self::found(stream_id, channel_name, timestamp, title, domain, link)
+        }))
+    }
+
+    /// Tries every start time within `window` seconds of `timestamp`, closest first, with the
+    /// requests spread across every CloudFront domain and at most `concurrency` in flight at
+    /// once. Returns the video along with the offset from `timestamp` that matched.
+    pub fn search(
+        stream_id: u64,
+        timestamp: i64,
+        channel_name: &str,
+        title: Option<String>,
+        window: u32,
+        concurrency: usize,
+    ) -> Result<Option<(Self, i64)>, Box<dyn std::error::Error>> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(concurrency)
+            .build()?;
+        Ok(pool
+            .install(|| Self::search_window(stream_id, timestamp, channel_name, title, window))?)
+    }
+
+    /// Same as `search`, but sends its requests from the current thread pool
+    fn search_window(
+        stream_id: u64,
+        timestamp: i64,
+        channel_name: &str,
+        title: Option<String>,
+        window: u32,
+    ) -> Result<Option<(Self, i64)>, String> {
+        let domains = domains();
+        let candidates = window_candidates(window, &domains);
+        let hit = first_existing(&candidates, |(offset, domain)| {
+            exists(&playlist_link(
+                domain,
+                channel_name,
+                stream_id,
+                timestamp + offset,
+            ))
+        })?;
 
-        cloudfront_link.map(|link| Self {
+        Ok(hit.map(|&(offset, domain)| {
+            let start = timestamp + offset;
+            let link = playlist_link(domain, channel_name, stream_id, start);
+            (
+                Self::found(stream_id, channel_name, start, title, domain, link),
+                offset,
+            )
+        }))
+    }
+
+    fn found(
+        stream_id: u64,
+        channel_name: &str,
+        start: i64,
+        title: Option<String>,
+        domain: &str,
+        link: String,
+    ) -> Self {
+        Self {
             id: stream_id,
-            link,
+            channel: channel_name.to_owned(),
+            start,
             title,
+            variants: crate::hls::probe_variants(&link),
+            link,
+            domain: domain.to_owned(),
+        }
+    }
+
+    pub fn is_match(&self, filter: &regex::Regex) -> bool {
+        filter.is_match(&self.id.to_string())
+            || filter.is_match(&self.link)
+            || match &self.title {
+                Some(title) => filter.is_match(title),
+                None => false,
+            }
+    }
+
+    /// Saves a playlist of the best available quality into `dir`, with every segment pointing
+    /// to its absolute CloudFront URL so that it can be played from anywhere, along with a JSON
+    /// file describing the stream. Returns the path of the playlist.
+    pub fn save(
+        &self,
+        dir: &std::path::Path,
+    ) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+        let quality = self
+            .variants
+            .first()
+            .map(|quality| quality.as_str())
+            .unwrap_or("chunked");
+        let url = crate::hls::with_quality(&self.link, quality).ok_or("Invalid playlist link")?;
+        let (url, media) = crate::hls::fetch_media(&url, None)?;
+
+        std::fs::create_dir_all(dir)?;
+        let playlist = dir.join(format!("{}.m3u8", self.id));
+        std::fs::write(&playlist, media.to_m3u8(&url))?;
+
+        let mut metadata = self.to_json();
+        metadata["quality"] = serde_json::json!(quality);
+        metadata["playlist"] = serde_json::json!(url);
+        metadata["duration"] = serde_json::json!(media.duration());
+        metadata["segments"] = serde_json::json!(media.segments.len());
+        std::fs::write(
+            dir.join(format!("{}.json", self.id)),
+            serde_json::to_string_pretty(&metadata)?,
+        )?;
+
+        Ok(playlist)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "channel": self.channel,
+            "start": self.start,
+            "title": self.title,
+            "link": self.link,
+            "domain": self.domain,
+            "variants": self.variants,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A new, empty directory for files written by a test
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat_reader-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn learned_domains_live_in_the_data_dir() {
+        let dir = temp_dir("data");
+        std::env::set_var("CHAT_READER_DATA", &dir);
+        assert_eq!(learned_path(), Some(dir.join("cloudfront_domains.json")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn merges_domains() {
+        let dir = temp_dir("domains-merge");
+        let path = dir.join("cloudfront_domains.json");
+        std::fs::write(&path, r#"["d2e2de1etea730", "dlearned", "dconfigured"]"#).unwrap();
+        let configured = [
+            serde_json::json!("dconfigured"),
+            serde_json::json!("dqrpb9wgowsf5"),
+            serde_json::json!("dother"),
+        ];
+
+        let domains = Domains::load(&configured, Some(path));
+        let mut expected: Vec<&str> = CLOUDFRONT_DOMAINS.to_vec();
+        expected.extend(["dconfigured", "dother", "dlearned"]);
+        assert_eq!(domains.all, expected);
+
+        // A missing or unreadable file only leaves out the learned domains
+        let domains = Domains::load(&configured, Some(dir.join("missing.json")));
+        assert_eq!(domains.all.len(), CLOUDFRONT_DOMAINS.len() + 2);
+        assert_eq!(Domains::load(&[], None).all, CLOUDFRONT_DOMAINS.to_vec());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn saves_only_learned_domains() {
+        let dir = temp_dir("domains-learn");
+        let path = dir.join("data").join("cloudfront_domains.json");
+        let mut domains = Domains::load(&[serde_json::json!("dconfigured")], Some(path.clone()));
+
+        assert_eq!(domains.learn("dconfigured"), Ok(false));
+        assert_eq!(domains.learn(CLOUDFRONT_DOMAINS[0]), Ok(false));
+        assert!(!path.exists());
+        assert_eq!(domains.learn("dnew"), Ok(true));
+        assert_eq!(domains.learn("dnew"), Ok(false));
+        assert_eq!(domains.all.last().map(String::as_str), Some("dnew"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), r#"["dnew"]"#);
+
+        // Removing a domain from the config stops it from being searched
+        let reloaded = Domains::load(&[], Some(path));
+        assert!(reloaded.all.contains(&String::from("dnew")));
+        assert!(!reloaded.all.contains(&String::from("dconfigured")));
+
+        let mut nowhere = Domains::load(&[], None);
+        assert!(nowhere.learn("dnew").is_err());
+        assert!(nowhere.all.contains(&String::from("dnew")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A stream's ID, start time and title
+    type Stream<'a> = (u64, i64, Option<&'a str>);
+
+    /// A stream history source that returns fixed streams, or fails
+    struct StubSource(Result<Vec<Stream<'static>>, &'static str>);
+
+    impl history::StreamHistorySource for StubSource {
+        fn name(&self) -> &str {
+            "stub"
+        }
+
+        fn streams(
+            &self,
+            channel: &str,
+        ) -> Result<Vec<history::StreamRecord>, Box<dyn std::error::Error>> {
+            let streams = self.0.clone()?;
+            Ok(streams
+                .into_iter()
+                .map(|(stream_id, start, title)| history::StreamRecord {
+                    stream_id,
+                    start,
+                    channel: channel.to_owned(),
+                    title: title.map(String::from),
+                })
+                .collect())
+        }
+    }
+
+    fn summary(streams: &[history::StreamRecord]) -> Vec<Stream<'_>> {
+        streams
+            .iter()
+            .map(|stream| (stream.stream_id, stream.start, stream.title.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn merges_streams() {
+        let channel = Channel::with_sources(
+            "twitch",
+            vec![
+                Box::new(StubSource(Ok(vec![
+                    (1, 100, None),
+                    (2, 300, Some("first")),
+                ]))),
+                Box::new(StubSource(Err("down"))),
+                Box::new(StubSource(Ok(vec![
+                    (1, 150, Some("filled")),
+                    (2, 350, Some("second")),
+                    (3, 200, None),
+                ]))),
+            ],
+        );
+        assert_eq!(
+            summary(&channel.streams().unwrap()),
+            [
+                (2, 300, Some("first")),
+                (3, 200, None),
+                (1, 100, Some("filled")),
+            ]
+        );
+
+        let channel = Channel::with_sources(
+            "twitch",
+            vec![
+                Box::new(StubSource(Err("down"))),
+                Box::new(StubSource(Err("gone"))),
+            ],
+        );
+        assert_eq!(
+            channel.streams().unwrap_err().to_string(),
+            "stub: down\nstub: gone"
+        );
+    }
+
+    #[test]
+    fn parses_local_files() {
+        let expected = [
+            (40000000001, 1656676800, Some("Just Chatting")),
+            (40000000002, 1656590400, None),
+        ];
+        let csv = history::LocalFile::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/streams.csv"
+        ));
+        let streams = history::StreamHistorySource::streams(&csv, "Twitch").unwrap();
+        assert_eq!(
+            summary(&streams),
+            [
+                (40000000001, 1656676800, Some("Just Chatting, then games")),
+                expected[1],
+            ]
+        );
+        assert!(streams.iter().all(|stream| stream.channel == "twitch"));
+
+        let json = history::LocalFile::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/streams.json"
+        ));
+        let streams = history::StreamHistorySource::streams(&json, "Twitch").unwrap();
+        assert_eq!(summary(&streams), expected);
+    }
+
+    #[test]
+    fn names_history_sources() {
+        let name = |spec| history::from_spec(spec).name().to_owned();
+        assert_eq!(name("sullygnome"), "sullygnome");
+        assert_eq!(name("sullygnome:730"), "sullygnome");
+        assert_eq!(name("twitchtracker"), "twitchtracker");
+        assert_eq!(name("sullygnome:forever"), "sullygnome:forever");
+        assert_eq!(name("streams.csv"), "streams.csv");
+    }
+
+    #[test]
+    fn searches_closest_offsets_first() {
+        let domains = [String::from("da"), String::from("db")];
+        assert_eq!(window_candidates(0, &domains), [(0, "da"), (0, "db")]);
+        assert_eq!(
+            window_candidates(2, &domains),
+            [
+                (0, "da"),
+                (0, "db"),
+                (-1, "da"),
+                (-1, "db"),
+                (1, "da"),
+                (1, "db"),
+                (-2, "da"),
+                (-2, "db"),
+                (2, "da"),
+                (2, "db"),
+            ]
+        );
+        assert_eq!(window_candidates(60, &domains).len(), 121 * 2);
+        assert!(window_candidates(5, &[]).is_empty());
+    }
+
+    #[test]
+    fn parses_batches() {
+        let input = "\n  # recovered from the mod logs\nUserName, Stream_ID, Start_Time\ntwitch,40000000001,2022-07-01T12:00:00Z\n\n  twitch , 40000000002 , 1656590400 ,extra\n# done\ntwitch,40000000003\n";
+        assert_eq!(
+            batch_rows(input),
+            [
+                (4, Ok(["twitch", "40000000001", "2022-07-01T12:00:00Z"])),
+                (6, Ok(["twitch", "40000000002", "1656590400"])),
+                (
+                    8,
+                    Err(String::from("Expected username,stream_id,start_time"))
+                ),
+            ]
+        );
+
+        // Only the first row can be a header, and only when it looks like one
+        let input = "twitch,abc,2022-07-01T12:00:00Z\nusername,stream_id,start_time";
+        assert_eq!(
+            batch_rows(input),
+            [
+                (1, Ok(["twitch", "abc", "2022-07-01T12:00:00Z"])),
+                (2, Ok(["username", "stream_id", "start_time"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_first_existing_candidate() {
+        let candidates: Vec<u32> = (0..64).collect();
+        let check = |&candidate: &u32| match candidate {
+            3 => Err(String::from("timed out")),
+            candidate => Ok(candidate % 10 == 7),
+        };
+        assert_eq!(first_existing(&candidates, check), Ok(Some(&7)));
+        assert_eq!(first_existing(&candidates[..7], |_| Ok(false)), Ok(None));
+        assert_eq!(
+            first_existing(&candidates[..7], check),
+            Err(String::from("1 of 7 requests failed, first: timed out"))
+        );
+        assert_eq!(first_existing(&[] as &[u32], check), Ok(None));
+    }
+}
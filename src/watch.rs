@@ -0,0 +1,376 @@
+use crate::common::Vod;
+use colored::Colorize;
+
+/// The VOD IDs that have already been indexed for every watched channel, kept on disk so that a
+/// restarted watcher picks up where it left off
+struct State {
+    path: std::path::PathBuf,
+    seen: std::collections::HashMap<String, std::collections::HashSet<String>>,
+}
+
+impl State {
+    fn load(path: &std::path::Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut seen = std::collections::HashMap::new();
+        if path.exists() {
+            let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            for (channel, ids) in json
+                .as_object()
+                .ok_or("Could not convert state -> object")?
+            {
+                let ids = ids
+                    .as_array()
+                    .ok_or("Could not convert ids -> array")?
+                    .iter()
+                    .flat_map(|id| id.as_str())
+                    .map(String::from)
+                    .collect();
+                seen.insert(channel.to_owned(), ids);
+            }
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            seen,
+        })
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let json: serde_json::Map<String, serde_json::Value> = self
+            .seen
+            .iter()
+            .map(|(channel, ids)| (channel.to_owned(), serde_json::json!(ids)))
+            .collect();
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(&json)?)?;
+        std::fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+pub struct Watcher<'a> {
+    state: State,
+    queries: Vec<(String, regex::Regex)>,
+    archive: Option<&'a crate::archive::Archive>,
+    alerts: Option<&'a crate::rules::Engine>,
+    backfill: bool,
+}
+
+impl<'a> Watcher<'a> {
+    /// Saved queries are read from the `queries` object of the config, mapping names to patterns.
+    /// A non-empty filter is run alongside them.
+    pub fn new(
+        state: &std::path::Path,
+        filter: &regex::Regex,
+        archive: Option<&'a crate::archive::Archive>,
+        alerts: Option<&'a crate::rules::Engine>,
+        backfill: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut queries = Vec::new();
+        if let Some(saved) = crate::config::CONFIG
+            .get("queries")
+            .and_then(|q| q.as_object())
+        {
+            for (name, pattern) in saved {
+                let pattern = pattern
+                    .as_str()
+                    .ok_or(format!("Query {} is not a string", name))?;
+                queries.push((name.to_owned(), regex::Regex::new(pattern)?));
+            }
+        }
+        if !filter.as_str().is_empty() {
+            queries.push((String::from("filter"), filter.clone()));
+        }
+
+        Ok(Self {
+            state: State::load(state)?,
+            queries,
+            archive,
+            alerts,
+            backfill,
+        })
+    }
+
+    /// Checks every channel once, indexing any VODs that have not been seen before
+    pub fn poll(&mut self, twitch: &[String], afreecatv: &[String]) {
+        for name in twitch {
+            let channel = crate::twitch::Channel::new(name);
+            // The VOD of a stream that is still live only has part of its chat, so it is left
+            // until a poll after the stream has ended
+            let videos = channel.live_vod().and_then(|live| {
+                Ok(channel
+                    .videos()?
+                    .into_iter()
+                    .filter(|vod| Some(vod.id()) != live)
+                    .collect::<Vec<crate::twitch::Vod>>())
+            });
+            match videos {
+                Ok(videos) => self.index(&format!("twitch/{}", name), &videos),
+                Err(e) => eprintln!("Unable to list videos for {}: {}", name, e),
+            }
+        }
+        for name in afreecatv {
+            match crate::afreecatv::Channel::new(name).videos() {
+                Ok(videos) => self.index(&format!("afreecatv/{}", name), &videos),
+                Err(e) => eprintln!("Unable to list videos for {}: {}", name, e),
+            }
+        }
+    }
+
+    fn index<V>(&mut self, channel: &str, videos: &[V])
+    where
+        V: Vod,
+    {
+        // The first time a channel is seen, its existing VODs are only recorded unless a backfill
+        // was requested
+        let first_poll = !self.state.seen.contains_key(channel);
+        for vod in videos {
+            let id = vod.id();
+            if self
+                .state
+                .seen
+                .get(channel)
+                .is_some_and(|seen| seen.contains(&id))
+            {
+                continue;
+            }
+
+            if !first_poll || self.backfill {
+                if let Err(e) = self.process(channel, vod) {
+                    eprintln!("Unable to index {}: {}", id, e);
+                    continue;
+                }
+            }
+
+            self.state
+                .seen
+                .entry(channel.to_owned())
+                .or_default()
+                .insert(id);
+            if let Err(e) = self.state.save() {
+                eprintln!("Unable to save state: {}", e);
+            }
+        }
+        if first_poll {
+            self.state.seen.entry(channel.to_owned()).or_default();
+            if let Err(e) = self.state.save() {
+                eprintln!("Unable to save state: {}", e);
+            }
+        }
+    }
+
+    fn process<V>(&self, channel: &str, vod: &V) -> Result<(), Box<dyn std::error::Error>>
+    where
+        V: Vod,
+    {
+        println!("New VOD from {}: {}", channel.bold(), vod);
+        let id = vod.id();
+        let source = format!("{}/{}", channel, id);
+        // A VOD whose chat could only be read in part is left unseen, so that it is read again in
+        // full on a later poll
+        let mut comments = vod.comments();
+        let messages: Vec<crate::common::Message> = comments.by_ref().flatten().collect();
+        if let Some(e) = comments.error() {
+            return Err(format!("Unable to read all of its chat: {}", e).into());
+        }
+
+        if let Some(archive) = self.archive {
+            archive.append(&source, &messages)?;
+        }
+        for message in &messages {
+            self.alerts
+                .iter()
+                .for_each(|a| a.process(channel, Some(&id), message));
+            for (name, query) in &self.queries {
+                if message.is_match(query) {
+                    println!("[{}] {}", name.green(), message);
+                }
+            }
+        }
+        println!();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chat that yields one page, then optionally fails
+    struct StubChat {
+        messages: Option<Vec<crate::common::Message>>,
+        error: Option<String>,
+    }
+
+    impl crate::common::ChatIterator for StubChat {
+        fn error(&self) -> Option<&str> {
+            self.error.as_deref()
+        }
+    }
+    impl Iterator for StubChat {
+        type Item = Vec<crate::common::Message>;
+        fn next(&mut self) -> Option<Self::Item> {
+            self.messages.take()
+        }
+    }
+
+    /// A VOD that counts how often its chat is read
+    struct StubVod {
+        id: &'static str,
+        fails: bool,
+        reads: std::sync::atomic::AtomicUsize,
+    }
+
+    impl StubVod {
+        fn new(id: &'static str) -> Self {
+            Self {
+                id,
+                fails: false,
+                reads: std::sync::atomic::AtomicUsize::new(0),
+            }
+        }
+
+        fn reads(&self) -> usize {
+            self.reads.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    impl std::fmt::Display for StubVod {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.id)
+        }
+    }
+
+    impl Vod for StubVod {
+        fn id(&self) -> String {
+            self.id.to_owned()
+        }
+
+        fn channel(&self) -> String {
+            String::from("stub")
+        }
+
+        fn comments(&self) -> Box<dyn crate::common::ChatIterator> {
+            self.reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Box::new(StubChat {
+                messages: Some(vec![crate::common::Message {
+                    body: String::from("hi"),
+                    ..Default::default()
+                }]),
+                error: self.fails.then(|| String::from("connection reset")),
+            })
+        }
+
+        fn link(&self, _: f64) -> String {
+            String::new()
+        }
+    }
+
+    /// The path of a state file in a new, empty directory
+    fn state_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat_reader-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("state.json")
+    }
+
+    fn watcher(state: &std::path::Path, backfill: bool) -> Watcher<'static> {
+        Watcher {
+            state: State::load(state).unwrap(),
+            queries: Vec::new(),
+            archive: None,
+            alerts: None,
+            backfill,
+        }
+    }
+
+    fn seen(watcher: &Watcher, channel: &str) -> Vec<String> {
+        let mut seen: Vec<String> = watcher.state.seen[channel].iter().cloned().collect();
+        seen.sort();
+        seen
+    }
+
+    #[test]
+    fn first_poll_only_records_existing_vods() {
+        let path = state_path("watch-first");
+        let mut watcher = watcher(&path, false);
+        let vods = [StubVod::new("1"), StubVod::new("2")];
+        watcher.index("twitch/a", &vods);
+        assert_eq!(vods.iter().map(StubVod::reads).sum::<usize>(), 0);
+        assert_eq!(seen(&watcher, "twitch/a"), ["1", "2"]);
+
+        // A channel without any VODs yet is still recorded, so its first VOD is indexed
+        watcher.index("twitch/b", &[] as &[StubVod]);
+        assert!(seen(&watcher, "twitch/b").is_empty());
+        let vods = [StubVod::new("3")];
+        watcher.index("twitch/b", &vods);
+        assert_eq!(vods[0].reads(), 1);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn backfill_indexes_existing_vods() {
+        let path = state_path("watch-backfill");
+        let mut watcher = watcher(&path, true);
+        let vods = [StubVod::new("1"), StubVod::new("2")];
+        watcher.index("twitch/a", &vods);
+        assert_eq!(vods.iter().map(StubVod::reads).sum::<usize>(), 2);
+        assert_eq!(seen(&watcher, "twitch/a"), ["1", "2"]);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn indexes_only_new_vods_after_restart() {
+        let path = state_path("watch-restart");
+        watcher(&path, false).index("twitch/a", &[StubVod::new("1"), StubVod::new("2")]);
+
+        let mut restarted = watcher(&path, false);
+        let vods = [StubVod::new("1"), StubVod::new("2"), StubVod::new("3")];
+        restarted.index("twitch/a", &vods);
+        let reads: Vec<usize> = vods.iter().map(StubVod::reads).collect();
+        assert_eq!(reads, [0, 0, 1]);
+        assert_eq!(seen(&restarted, "twitch/a"), ["1", "2", "3"]);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn retries_vods_with_incomplete_chat() {
+        let path = state_path("watch-retry");
+        let mut watcher = watcher(&path, false);
+        watcher.index("twitch/a", &[] as &[StubVod]);
+
+        let mut vod = StubVod::new("1");
+        vod.fails = true;
+        watcher.index("twitch/a", std::slice::from_ref(&vod));
+        assert_eq!(vod.reads(), 1);
+        assert!(seen(&watcher, "twitch/a").is_empty());
+        assert!(State::load(&path).unwrap().seen["twitch/a"].is_empty());
+
+        vod.fails = false;
+        watcher.index("twitch/a", std::slice::from_ref(&vod));
+        assert_eq!(vod.reads(), 2);
+        assert_eq!(seen(&watcher, "twitch/a"), ["1"]);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn saves_and_loads_state() {
+        let path = state_path("watch-state");
+        let mut state = State::load(&path).unwrap();
+        assert!(state.seen.is_empty());
+
+        state
+            .seen
+            .entry(String::from("twitch/a"))
+            .or_default()
+            .extend([String::from("1"), String::from("2")]);
+        state.seen.entry(String::from("afreecatv/b")).or_default();
+        state.save().unwrap();
+        assert!(!path.with_extension("tmp").exists());
+
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.seen, state.seen);
+
+        std::fs::write(&path, "[]").unwrap();
+        assert!(State::load(&path).is_err());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
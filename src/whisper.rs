@@ -28,7 +28,7 @@ pub fn process(
     let mut displayed_title = false;
     for line in reader
         .lines()
-        .filter_map(|line| line.ok())
+        .map_while(Result::ok)
         .filter(|line| filter.is_match(line))
     {
         if !displayed_title {
@@ -37,6 +37,7 @@ pub fn process(
         }
         writeln!(task, "{}", line)
     }
+    let _ = process.wait();
 }
 
 fn has_whisper() -> bool {